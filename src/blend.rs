@@ -0,0 +1,84 @@
+// Ways of putting one colour on top of another, like in the layer panel of any painting program.
+// Every channel is blended on its own, below is what's already there and above what's painted or laid over it.
+
+use cursive::theme::Color;
+
+use crate::palette::to_rgb;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Blend {
+    Normal,
+    Multiply,
+    Screen,
+    Overlay,
+    Add,
+    Darken,
+    Lighten,
+    Difference,
+}
+
+const NAMES: [(&str, Blend); 8] = [
+    ("normal", Blend::Normal),
+    ("multiply", Blend::Multiply),
+    ("screen", Blend::Screen),
+    ("overlay", Blend::Overlay),
+    ("add", Blend::Add),
+    ("darken", Blend::Darken),
+    ("lighten", Blend::Lighten),
+    ("difference", Blend::Difference),
+];
+
+impl Blend {
+    pub fn parse(name: &str) -> Option<Blend> {
+        NAMES.iter().find(|(n, _)| *n == name).map(|(_, b)| *b)
+    }
+
+    pub fn name(self) -> &'static str {
+        NAMES.iter().find(|(_, b)| *b == self).map(|(n, _)| *n).unwrap_or("normal")
+    }
+
+    fn channel(self, below: u8, above: u8) -> u8 {
+        let (b, a) = (below as u32, above as u32);
+        let value = match self {
+            Blend::Normal => a,
+            Blend::Multiply => a * b / 255,
+            Blend::Screen => 255 - (255 - a) * (255 - b) / 255,
+            // Multiply in the shadows and screen in the highlights of what is below.
+            Blend::Overlay if b < 128 => 2 * a * b / 255,
+            Blend::Overlay => 255 - 2 * (255 - a) * (255 - b) / 255,
+            Blend::Add => (a + b).min(255),
+            Blend::Darken => a.min(b),
+            Blend::Lighten => a.max(b),
+            Blend::Difference => a.abs_diff(b),
+        };
+
+        value as u8
+    }
+
+    pub fn apply(self, below: Color, above: Color) -> Color {
+        if self == Blend::Normal {
+            return above;
+        }
+
+        let (r, g, b) = to_rgb(below);
+        let (r2, g2, b2) = to_rgb(above);
+        Color::Rgb(self.channel(r, r2), self.channel(g, g2), self.channel(b, b2))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn channels() {
+        let grey = Color::Rgb(128, 128, 128);
+        assert!(Blend::Multiply.apply(Color::Rgb(255, 100, 0), grey) == Color::Rgb(128, 50, 0));
+        assert!(Blend::Screen.apply(Color::Rgb(0, 255, 128), grey) == Color::Rgb(128, 255, 192));
+        assert!(Blend::Add.apply(Color::Rgb(200, 0, 10), grey) == Color::Rgb(255, 128, 138));
+        assert!(Blend::Difference.apply(Color::Rgb(0, 200, 128), grey) == Color::Rgb(128, 72, 0));
+        assert!(Blend::Overlay.apply(Color::Rgb(0, 255, 64), grey) == Color::Rgb(0, 255, 64));
+        assert!(Blend::Normal.apply(Color::Rgb(1, 2, 3), grey) == grey);
+        assert!(NAMES.iter().all(|(n, b)| Blend::parse(n) == Some(*b) && b.name() == *n));
+    }
+}
@@ -0,0 +1,51 @@
+// The brush used to live in two static muts, written to from the colour picker and read from the canvas.
+// Now it's one shared piece of state, handed to every view that needs it when a canvas is set up.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use cursive::theme::Color;
+
+use crate::blend::Blend;
+use crate::canvas::{Cell, Part, Tool};
+
+#[derive(Clone, Copy)]
+pub struct Brush {
+    pub color: Color,
+    pub backcolor: Color,
+    pub symbol: char,
+    pub tool: Tool,
+    pub size: usize,
+    pub shape: Shape,
+    pub dots: u8, // The braille dots the braille tool sets or clears, dot 1 is the lowest bit.
+    pub alpha: u8, // How much the painted background covers what is below, see Cell.
+    pub blend: Blend,
+    pub erase: Part, // What the eraser takes away.
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Shape {
+    Square,
+    Circle,
+    Line,  // A horizontal line as wide as the brush size.
+    Stamp, // The canvas' stamp, a block of cells taken from a selection.
+}
+
+pub type SharedBrush = Rc<RefCell<Brush>>;
+
+impl Default for Brush {
+    fn default() -> Self {
+        Brush {color: Color::Rgb(0,0,0), backcolor: Color::Rgb(0,0,0), symbol: ' ', tool: Tool::Draw, size: 1, shape: Shape::Square, dots: 1, alpha: 255, blend: Blend::Normal, erase: Part::All}
+    }
+}
+
+impl Brush {
+    pub fn shared() -> SharedBrush {
+        Rc::new(RefCell::new(Brush::default()))
+    }
+
+    pub fn cell(&self) -> Cell {
+        // What a single dab of this brush leaves on the canvas.
+        Cell {color: self.color, backcolor: self.backcolor, symbol: self.symbol, alpha: self.alpha, ..Cell::default()}
+    }
+}
@@ -1,6 +1,6 @@
 // Here, we define our own Canvas, Colour Picker and associated functions.
 
-use image::{RgbImage, Rgb, DynamicImage};
+use image::{Rgb, RgbImage, RgbaImage, Rgba, DynamicImage};
 
 use cursive::direction::Direction;
 use cursive::event::{Event, EventResult, MouseButton, MouseEvent};
@@ -9,10 +9,15 @@ use cursive::Printer;
 use cursive::Vec2;
 use cursive::view::CannotFocus;
 
-// The big weak point of my implementation is the following.
-// These should be properties of a View, but I don't know how to access them from inside the event handler of different objects.
-static mut BRUSHCOLOR: Color = Color::Rgb(0,0,0);
-static mut BRUSHBACKCOLOR: Color = Color::Rgb(0,0,0);
+use crate::blend::Blend;
+use crate::brush::{Shape, SharedBrush};
+use crate::dither;
+use crate::export;
+use crate::history::History;
+use crate::import;
+use crate::kkun;
+use crate::palette;
+use crate::shapes;
 
 
 fn hsv_to_rbg(h: u16, s:f32, v:f32) -> (u8, u8, u8) {
@@ -49,11 +54,13 @@ fn hsv_to_rbg(h: u16, s:f32, v:f32) -> (u8, u8, u8) {
     }
 }
 
+#[allow(dead_code)]
 #[derive(Clone, Copy)]
 pub struct Options {
     pub size: Vec2,
 }
 
+#[derive(Clone)]
 pub struct Board {
     pub size: Vec2,
     pub cells: Vec<Cell>,
@@ -63,24 +70,205 @@ pub struct Board {
 pub struct Cell {
     pub color: Color,
     pub backcolor: Color,
-    pub symbol: char
+    pub symbol: char,
+    pub alpha: u8,       // How much the background covers what is below, 0 means there is no background at all.
+    pub no_symbol: bool, // The symbol below shows through, unlike a blank which hides it.
+    pub no_color: bool,  // The symbol is drawn in the colour of the one below.
 }
 
-#[derive(Clone, Copy)]
+impl Default for Cell {
+    // The blank white cell every canvas starts out with.
+    fn default() -> Self {
+        Cell {color: Color::Rgb(255, 255, 255), backcolor: Color::Rgb(255, 255, 255), symbol: ' ', alpha: 255, no_symbol: false, no_color: false}
+    }
+}
+
+impl Cell {
+    // Nothing at all, what new layers are filled with and erasing leaves behind on them.
+    pub const CLEAR: Cell = Cell {color: Color::Rgb(255, 255, 255), backcolor: Color::Rgb(255, 255, 255), symbol: ' ', alpha: 0, no_symbol: true, no_color: true};
+}
+
+fn mix(under: Color, over: Color, amount: u32) -> Color {
+    // Goes from under to over as amount goes from 0 to 255.
+    let (r, g, b) = palette::to_rgb(under);
+    let (r2, g2, b2) = palette::to_rgb(over);
+    let m = |x: u8, y: u8| ((x as u32 * (255 - amount) + y as u32 * amount + 127) / 255) as u8;
+    Color::Rgb(m(r, r2), m(g, g2), m(b, b2))
+}
+
+fn under_symbol(below: Cell) -> Option<Color> {
+    // The colour a symbol painted over this cell would be blended with. A blank's colour is never seen, so its background counts.
+    if !below.no_symbol && below.symbol != ' ' {
+        if below.no_color {None} else {Some(below.color)}
+    }
+    else if below.alpha > 0 {
+        Some(below.backcolor)
+    }
+    else {
+        None
+    }
+}
+
+pub fn stack(below: Cell, above: Cell, opacity: u8, blend: Blend) -> Cell {
+    // Puts a cell from a layer with the given opacity (0 to 100) and blend mode on top of another one.
+    let a = above.alpha as u32 * opacity as u32 / 100;
+
+    // Blending only has something to work with where there is a colour below.
+    // A symbol is blended with the symbol below, or with the background where there is none to be seen.
+    let above = Cell {
+        backcolor: if below.alpha > 0 {blend.apply(below.backcolor, above.backcolor)} else {above.backcolor},
+        color: match under_symbol(below) {
+            Some(c) => blend.apply(c, above.color),
+            None => above.color,
+        },
+        ..above
+    };
+
+    // The backgrounds are laid over each other like panes of tinted glass.
+    let alpha = a + below.alpha as u32 * (255 - a) / 255;
+    let backcolor = match (a, alpha) {
+        (0, _) => below.backcolor,
+        (_, 0) => above.backcolor,
+        _ => mix(below.backcolor, above.backcolor, a * 255 / alpha),
+    };
+
+    // A symbol is drawn over whatever is below, without a colour of its own in the colour of the symbol below.
+    // Blanks and missing symbols only hide what is below where the background covers it fully.
+    let (symbol, no_symbol, color, no_color) = if !above.no_symbol && above.symbol != ' ' {
+        match above.no_color {
+            true => (above.symbol, false, below.color, below.no_color),
+            false => (above.symbol, false, mix(backcolor, above.color, opacity as u32 * 255 / 100), false),
+        }
+    }
+    else if a == 255 {
+        (' ', above.no_symbol, above.color, above.no_color)
+    }
+    else {
+        (below.symbol, below.no_symbol, below.color, below.no_color)
+    };
+
+    Cell {color, backcolor, symbol, alpha: alpha as u8, no_symbol, no_color}
+}
+
+#[derive(Clone, PartialEq)]
+pub struct Layer {
+    pub name: String,
+    pub visible: bool,
+    pub opacity: u8, // In percent.
+    pub locked: bool,
+    pub blend: Blend,
+}
+
+impl Layer {
+    pub fn new(name: &str) -> Self {
+        Layer {name: name.to_string(), visible: true, opacity: 100, locked: false, blend: Blend::Normal}
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
 pub enum Tool {
     Draw,
     Pipette,
-    // Fill,  TODO
+    Fill,
+    Line,
+    Rectangle,
+    FilledRectangle,
+    Ellipse,
+    FilledEllipse,
+    Select,
+    Braille,
+    Erase,
+}
 
+struct Floating {
+    // A block of cells hovering over the canvas, from a paste or while a selection is moved.
+    pos: Vec2,
+    board: Board,
+    origin: Option<(Vec2, Vec2)>, // The area it was lifted from, which is only cleared once it's put down.
+    grab: Option<Vec2>,           // Where the mouse holds it, relative to pos.
+    part: Part,                   // What it replaces of the cells underneath.
+}
+
+impl Floating {
+    fn contains(&self, pos: Vec2) -> bool {
+        pos.fits_in_rect(self.pos, self.board.size)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Part {
+    All,
+    Background, // Only the background colour.
+    Foreground, // Only the character colour.
+    Symbols,    // The symbol and its colour, the background stays.
+}
+
+impl Part {
+    fn over(self, under: Cell, over: Cell) -> Cell {
+        // Whatever over leaves out or only partly covers keeps what was underneath, so pastes keep their holes.
+        let stacked = stack(under, over, 100, Blend::Normal);
+        match self {
+            Part::All => stacked,
+            Part::Background => Cell {backcolor: stacked.backcolor, alpha: stacked.alpha, ..under},
+            Part::Foreground if over.no_color => under,
+            Part::Foreground => Cell {color: over.color, no_color: false, ..under},
+            Part::Symbols if over.no_symbol => under,
+            Part::Symbols => Cell {symbol: over.symbol, no_symbol: false, color: stacked.color, no_color: stacked.no_color, ..under},
+        }
+    }
+
+    pub fn erase(self, cell: Cell, blank: Cell) -> Cell {
+        // Puts back the blank cell, but only the part of it that is being erased.
+        match self {
+            Part::All => blank,
+            Part::Background => Cell {backcolor: blank.backcolor, alpha: blank.alpha, ..cell},
+            Part::Foreground => Cell {color: blank.color, no_color: blank.no_color, ..cell},
+            Part::Symbols => Cell {symbol: blank.symbol, no_symbol: blank.no_symbol, color: blank.color, no_color: blank.no_color, ..cell},
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum FillMode {
+    // Which part of a cell has to match for the bucket to spread into it.
+    Background,
+    Foreground,
+    Symbol,
+    Cell,
+}
+
+#[derive(Clone, Copy)]
+pub struct FillOptions {
+    pub mode: FillMode,
+    pub tolerance: u8,  // Largest allowed difference in any colour channel, 0 means exact.
+    pub diagonal: bool, // 8-connectivity instead of 4.
+}
+
+impl Default for FillOptions {
+    fn default() -> Self {
+        FillOptions {mode: FillMode::Background, tolerance: 0, diagonal: false}
+    }
+}
+
+fn color_distance(a: Color, b: Color) -> u8 {
+    // The biggest difference of any of the channels, so a tolerance reads like "how many shades off".
+    match (a, b) {
+        (Color::Rgb(r, g, b), Color::Rgb(r2, g2, b2)) => {
+            let d = |x: u8, y: u8| (x as i16 - y as i16).unsigned_abs() as u8;
+            d(r, r2).max(d(g, g2)).max(d(b, b2))
+        }
+        _ => if a == b {0} else {255},
+    }
 }
 
 impl Board {
+    #[allow(clippy::redundant_field_names, clippy::let_and_return)]
     pub fn new(size: Vec2) -> Self {
         let n_cells = size.x * size.y;
 
         let board = Board {
             size: size,
-            cells: vec![Cell {color: Color::Rgb(255,255,255), backcolor: Color::Rgb(255,255,255), symbol: ' ' }; n_cells],
+            cells: vec![Cell::default(); n_cells],
         };
 
         board
@@ -94,89 +282,226 @@ impl Board {
         }
     }
 
-    /*
-    This is still from the the days where this was the minesweeper demo. I figure it might be useful to implement something like a fill tool.
-    pub fn neighbours(&self, pos: Vec2) -> Vec<Vec2> {
+    pub fn neighbours(&self, pos: Vec2, diagonal: bool) -> Vec<Vec2> {
+        // This is still from the the days where this was the minesweeper demo, now it's what the fill tool spreads along.
         let pos_min = pos.saturating_sub((1, 1));
         let pos_max = (pos + (2, 2)).or_min(self.size);
-        (pos_min.x..pos_max.x)        //img = img.thumbnail(200, 100);
+        (pos_min.x..pos_max.x)
             .flat_map(|x| (pos_min.y..pos_max.y).map(move |y| Vec2::new(x, y)))
             .filter(|&p| p != pos)
+            .filter(|&p| diagonal || p.x == pos.x || p.y == pos.y)
             .collect()
     }
-    */
+}
+
+fn cells_image(size: Vec2, cells: &[Cell]) -> RgbaImage {
+    // a default (black) image containing Rgba values, a cell's alpha is the alpha of its background
+    let mut image = RgbaImage::new(size.x as u32, (2 * size.y) as u32);
+
+    for (i, cell) in cells.iter().enumerate() {
+        let x = (i % size.x) as u32;
+        let y = (i / size.x) as u32;
+
+        // Half blocks are two pixels of their own, one in each colour.
+        let ink = if cell.no_color {0} else {255};
+        let (top, bottom) = match cell.symbol {
+            '▀' => ((cell.color, ink), (cell.backcolor, cell.alpha)),
+            '▄' => ((cell.backcolor, cell.alpha), (cell.color, ink)),
+            _ => ((cell.backcolor, cell.alpha), (cell.backcolor, cell.alpha)),
+        };
+        if let ((Color::Rgb(r, g, b), a), (Color::Rgb(r2, g2, b2), a2)) = (top, bottom) {
+            image.put_pixel(x, 2 * y, Rgba([r,g,b,a]));
+            image.put_pixel(x, 2 * y + 1, Rgba([r2,g2,b2,a2]));
+        }
+    }
+
+    image
 }
 
 pub struct CanvasView {
     board: Board,
 
-    overlay: Vec<Cell>,
-    history_o: Vec<Vec<Cell>>,
-    history_b: Vec<Board>,
+    overlay: Vec<Cell>, // The cells of the active layer, the others wait in stack.
+    layers: Vec<Layer>, // From the bottom up.
+    stack: Vec<Vec<Cell>>,
+    active: usize,
+    history: History,
     focused: Option<Vec2>,
-
-    brushsymbol: char,
-    tool: Tool
-    //In an ideal world, this is where brush(back)color would be :^)
+    anchor: Option<Vec2>, // Where a shape was started, while it is being dragged.
+    selection: Option<(Vec2, Vec2)>, // Top left and bottom right corner, both included.
+    floating: Option<Floating>,
+    clipboard: Option<Board>,
+    start: Vec2, // The size the canvas was made with, pictures are fitted to it.
+    adding: bool, // Whether the braille tool sets its dots or takes them away, for the length of one stroke.
+    erasing: bool, // Whether the current stroke erases, with the eraser or the right mouse button.
+    touched: Vec<bool>, // Cells the current stroke already painted, so a big brush doesn't blend them over and over.
+    hover: Option<Vec2>, // Where the brush is during a stroke, what it covers is shown there.
+    stamp: Option<Board>,
+    refused: bool, // Something was asked of a locked layer.
+
+    brush: SharedBrush,
+    fill: FillOptions,
+    meta: Vec<(String, String)>,
 }
 
 impl CanvasView {
-    pub fn new(size: Vec2) -> Self {
-        let overlay = vec![Cell {color: Color::Rgb(255, 255, 255), backcolor: Color::Rgb(255, 255, 255), symbol: ' '} ; size.x * size.y];
-        let history_o = vec![Cell {color: Color::Rgb(255, 255, 255), backcolor: Color::Rgb(255, 255, 255), symbol: ' '} ; size.x * size.y];
+    pub fn new(size: Vec2, brush: SharedBrush) -> Self {
+        let overlay = vec![Cell::default(); size.x * size.y];
         let board = Board::new(size);
-        let history_b = Board::new(size);
+        let layers = vec![Layer::new("Background")];
+        let history = History::new(size, &overlay, &layers);
         CanvasView {
             board,
             overlay,
-            history_o: vec![history_o], // The 250 means that we're keeping history for 250 steps.
-            history_b: vec![history_b],
+            layers,
+            stack: vec![Vec::new()],
+            active: 0,
+            history,
             focused: None,
-            brushsymbol: ' ',
-            tool: Tool::Draw
+            anchor: None,
+            selection: None,
+            floating: None,
+            clipboard: None,
+            start: size,
+            adding: true,
+            erasing: false,
+            touched: Vec::new(),
+            hover: None,
+            stamp: None,
+            refused: false,
+            brush,
+            fill: FillOptions::default(),
+            meta: Vec::new(),
         }
     }
 
     pub fn changecolor(&mut self, pos: Vec2, color: Color, backcolor: Color) {
         if let Some(i) = self.board.cell_id(pos) {
-            let symbol: char = self.brushsymbol;
-            let new_cell = Cell {color, backcolor, symbol};
+            // The brush's blend mode mixes the new colours with the old ones, as long as there are any.
+            let brush = *self.brush.borrow();
+            let old = self.overlay[i];
+            let color = match under_symbol(old) {
+                Some(c) => brush.blend.apply(c, color),
+                None => color,
+            };
+            let backcolor = if old.alpha == 0 {backcolor} else {brush.blend.apply(old.backcolor, backcolor)};
+            let new_cell = Cell {color, backcolor, symbol: brush.symbol, alpha: brush.alpha, ..Cell::default()};
 
             self.overlay[i] = new_cell;
         }
     }
 
     pub fn changebrushsymbol(&mut self, symbol: char) {
-        self.brushsymbol = symbol;
+        self.brush.borrow_mut().symbol = symbol;
+    }
+
+    pub fn get_brush(&self) -> SharedBrush {
+        self.brush.clone()
+    }
+
+    fn tool(&self) -> Tool {
+        self.brush.borrow().tool
     }
 
     pub fn clear(&mut self) {
-        self.overlay = vec![Cell {color: Color::Rgb(255, 255, 255), backcolor: Color::Rgb(255, 255, 255), symbol: ' '} ; self.board.size.x * self.board.size.y]
+        if self.refuse() {
+            return;
+        }
+
+        self.overlay = vec![self.blank(); self.board.size.x * self.board.size.y];
+        self.add_history();
+    }
+
+    fn blank(&self) -> Cell {
+        // What clearing leaves behind: white on the bottom layer, nothing on the ones above.
+        if self.active == 0 {Cell::default()} else {Cell::CLEAR}
     }
 
     pub fn fill_all(&mut self) {
         // Applies the current brush to all cells of the canvas.
+        if self.refuse() {
+            return;
+        }
+
+        self.overlay  = vec![self.brush.borrow().cell(); self.board.size.x * self.board.size.y];
         self.add_history();
-        unsafe {
-            self.overlay  = vec![Cell {color: BRUSHCOLOR, backcolor: BRUSHBACKCOLOR, symbol: self.brushsymbol}; self.board.size.x * self.board.size.y]
+    }
+
+    pub fn flood_fill(&mut self, pos: Vec2) {
+        // Paints the brush into the contiguous region around pos, as decided by the fill options.
+        if self.refuse() {
+            return;
         }
+
+        let start = match self.board.cell_id(pos) {
+            Some(i) => i,
+            None => return,
+        };
+
+        let target = self.overlay[start];
+        let new_cell = self.brush.borrow().cell();
+        let options = self.fill;
+
+        let matches = |cell: &Cell| match options.mode {
+            FillMode::Background => color_distance(cell.backcolor, target.backcolor) <= options.tolerance,
+            FillMode::Foreground => color_distance(cell.color, target.color) <= options.tolerance,
+            FillMode::Symbol => cell.symbol == target.symbol,
+            FillMode::Cell => cell.symbol == target.symbol
+                && color_distance(cell.color, target.color) <= options.tolerance
+                && color_distance(cell.backcolor, target.backcolor) <= options.tolerance,
+        };
+
+        // A plain stack instead of recursion, large canvases would otherwise overflow.
+        let mut visited = vec![false; self.overlay.len()];
+        let mut stack = vec![pos];
+        visited[start] = true;
+
+        while let Some(p) = stack.pop() {
+            let i = p.x + p.y * self.board.size.x;
+            self.overlay[i] = new_cell;
+
+            for n in self.board.neighbours(p, options.diagonal) {
+                let j = n.x + n.y * self.board.size.x;
+                if !visited[j] && matches(&self.overlay[j]) {
+                    visited[j] = true;
+                    stack.push(n);
+                }
+            }
+        }
+
+        self.add_history();
     }
 
     pub fn fliph(&mut self) {
-        // Currently only flips the background colours.
+        // Mirrors the active layer left to right. Whole cells move, so what's see-through stays see-through.
 
-        let rgbimg = self.toimage();
-        let overlay_old = self.get_overlay();
-        let image_flipped = DynamicImage::ImageRgb8(rgbimg).fliph();
-        self.fromimage(image_flipped, Some(overlay_old));
+        if self.refuse() {
+            return;
+        }
+
+        let w = self.board.size.x;
+        for row in self.overlay.chunks_mut(w) {
+            row.reverse();
+        }
+        for cell in self.overlay.iter_mut() {
+            cell.symbol = match cell.symbol {'▌' => '▐', '▐' => '▌', c => c};
+        }
+        self.add_history();
     }
     pub fn flipv(&mut self) {
-        // Currently only flips the background colours.
+        // Mirrors the active layer top to bottom, half blocks turn over with it.
 
-        let rgbimg = self.toimage();
-        let overlay_old = self.get_overlay();
-        let image_flipped = DynamicImage::ImageRgb8(rgbimg).flipv();
-        self.fromimage(image_flipped, Some(overlay_old));
+        if self.refuse() {
+            return;
+        }
+
+        let w = self.board.size.x;
+        let rows: Vec<Vec<Cell>> = self.overlay.chunks(w).rev().map(|r| r.to_vec()).collect();
+        self.overlay = rows.concat();
+        for cell in self.overlay.iter_mut() {
+            cell.symbol = match cell.symbol {'▀' => '▄', '▄' => '▀', c => c};
+        }
+        self.add_history();
     }
 
     /*
@@ -192,7 +517,11 @@ impl CanvasView {
     */
 
     pub fn grayscale(&mut self) {
-        let rgbimg = self.toimage();
+        if self.refuse() {
+            return;
+        }
+
+        let rgbimg = self.layerimage();
         let overlay_old = self.get_overlay();
         let greyimage = DynamicImage::ImageRgb8(rgbimg).grayscale();
         self.fromimage(greyimage, Some(overlay_old));
@@ -201,24 +530,63 @@ impl CanvasView {
     pub fn blur(&mut self, sigma: f32) {
         // Blurs background colours.
 
-        let rgbimg = self.toimage();
+        if self.refuse() {
+            return;
+        }
+
+        // Colours count as much as they cover, so see-through cells don't bleed their white into the ones around them.
+        let mut rgbaimg = cells_image(self.board.size, &self.overlay);
+        for p in rgbaimg.pixels_mut() {
+            for c in 0..3 {
+                p[c] = (p[c] as u32 * p[3] as u32 / 255) as u8;
+            }
+        }
+
+        let blurred = DynamicImage::ImageRgba8(rgbaimg).blur(sigma).into_rgba8();
+        let img_blurred = RgbImage::from_fn(blurred.width(), blurred.height(), |x, y| {
+            let p = blurred.get_pixel(x, y);
+            let c = |v: u8| if p[3] == 0 {255} else {(v as u32 * 255 / p[3] as u32).min(255) as u8};
+            Rgb([c(p[0]), c(p[1]), c(p[2])])
+        });
         let overlay_old = self.get_overlay();
-        let img_blurred = DynamicImage::ImageRgb8(rgbimg).blur(sigma);
-        self.fromimage(img_blurred, Some(overlay_old));
+        self.fromimage(DynamicImage::ImageRgb8(img_blurred), Some(overlay_old));
     }
 
     pub fn rotate90(&mut self) {
-        // TODO: Only handles the background, crashes if applied twice - probably due to fromimage()
+        // Turns the whole canvas, every layer with it. Cells are twice as high as wide, so this goes by pixels and only
+        // the backgrounds come along, with how much they cover. A locked layer anywhere keeps all of them as they are.
+        if self.layers.iter().any(|l| l.locked) {
+            self.refused = true;
+            return;
+        }
 
-        let rgbimg = self.toimage();
-        let img_rot = DynamicImage::ImageRgb8(rgbimg).rotate90();
-        self.fromimage(img_rot, None);
+        self.commit_floating();
+        let old = self.board.size;
+        let size = Vec2::new(2 * old.y, (old.x / 2).max(1));
+        let stack = (0..self.layers.len()).map(|i| {
+            let blank = if i == 0 {Cell::default()} else {Cell::CLEAR};
+            let rotated = DynamicImage::ImageRgba8(cells_image(old, self.layer_cells(i))).rotate90().into_rgba8();
+            (0..size.x * size.y).map(|j| {
+                let p = rotated.get_pixel((j % size.x) as u32, (2 * (j / size.x) as u32).min(rotated.height() - 1));
+                match p[3] {
+                    0 => Cell::CLEAR,
+                    a => Cell {backcolor: Color::Rgb(p[0], p[1], p[2]), alpha: a, ..blank},
+                }
+            }).collect()
+        }).collect();
+
+        self.set_layers(size, self.layers.clone(), stack, self.active);
+        self.add_history();
     }
 
     pub fn brighten(&mut self, value: i32) {
         //  Brightens or darkens the canvas by the given value.
 
-        let rgbimg = self.toimage();
+        if self.refuse() {
+            return;
+        }
+
+        let rgbimg = self.layerimage();
         let overlay_old = self.get_overlay();
         let img_bright = DynamicImage::ImageRgb8(rgbimg).brighten(value);
         self.fromimage(img_bright, Some(overlay_old));
@@ -227,7 +595,11 @@ impl CanvasView {
     pub fn adjust_contrast(&mut self, c: f32) {
         // Adjusts the background contrast by c.
 
-        let rgbimg = self.toimage();
+        if self.refuse() {
+            return;
+        }
+
+        let rgbimg = self.layerimage();
         let overlay_old = self.get_overlay();
         let img_contrast = DynamicImage::ImageRgb8(rgbimg).adjust_contrast(c);
         self.fromimage(img_contrast, Some(overlay_old));
@@ -235,62 +607,76 @@ impl CanvasView {
 
     pub fn huerotate(&mut self, value: i32) {
         // Rotates the background hues by value.
-        // TODO: So this is weird, image's huerotate also darkens the colours. I added a self.brighten to counteract.
+        // TODO: So this is weird, image's huerotate also darkens the colours. I added a brighten to counteract.
 
-        self.brighten(1);
-        let rgbimg = self.toimage();
+        if self.refuse() {
+            return;
+        }
+
+        let rgbimg = self.layerimage();
         let overlay_old = self.get_overlay();
-        let img_hue = DynamicImage::ImageRgb8(rgbimg).huerotate(value);
+        let img_hue = DynamicImage::ImageRgb8(rgbimg).brighten(1).huerotate(value);
         self.fromimage(img_hue, Some(overlay_old));
     }
 
-    pub fn toimage(&mut self) -> RgbImage {
-        // a default (black) image containing Rgb values
-        let mut image = RgbImage::new(self.board.size.x as u32, (2 * self.board.size.y) as u32);
+    pub fn dither(&mut self, colors: &[(u8, u8, u8)], dither: dither::Dither) {
+        // Brings the canvas down to a few colours. Backgrounds and half blocks are dithered, character colours only rounded to the closest.
+        if self.refuse() {
+            return;
+        }
 
-        for (i, cell) in self.overlay.iter().enumerate() {
-            let x = (i % self.board.size.x) as u32;
-            let y = (i / self.board.size.x) as u32;
+        let mut overlay_old = self.get_overlay();
+        for cell in overlay_old.iter_mut().filter(|c| c.symbol != '▀' && c.symbol != '▄') {
+            let (r, g, b) = colors[palette::nearest(colors, palette::to_rgb(cell.color))];
+            cell.color = Color::Rgb(r, g, b);
+        }
 
-            let col = cell.backcolor;
-            match col {
-                Color::Rgb(r, g, b) => {
-                    image.put_pixel(x as u32, (2 * y) as u32, Rgb([r,g,b]));
-                    image.put_pixel(x as u32, (2 * y + 1) as u32, Rgb([r,g,b]));
-                }
+        let mut rgbimg = self.layerimage();
+        dither::quantize_where(&mut rgbimg, colors, dither, Some(&self.layermask()));
+        self.fromimage(DynamicImage::ImageRgb8(rgbimg), Some(overlay_old));
+    }
 
-                _ => continue
-            }
-        }
+    pub fn toimage(&mut self) -> RgbaImage {
+        // All visible layers, as they are seen, but where nothing covers the canvas it stays transparent.
+        cells_image(self.board.size, &self.composite_with(&self.overlay, Cell::CLEAR))
+    }
 
-        image
+    fn layerimage(&self) -> RgbImage {
+        // Only the active layer, which is what the filters work on.
+        DynamicImage::ImageRgba8(cells_image(self.board.size, &self.overlay)).into_rgb8()
+    }
+
+    fn layermask(&self) -> Vec<bool> {
+        // The pixels of layerimage that can be seen at all. The others are white, but only because they have to be something.
+        cells_image(self.board.size, &self.overlay).pixels().map(|p| p[3] > 0).collect()
+    }
+
+    pub fn torender(&mut self, cell: Vec2) -> (RgbImage, usize) {
+        // Unlike toimage, this draws the symbols too. The second value counts symbols missing from the font.
+        export::to_render(self.board.size, &self.composite(), cell)
     }
 
     pub fn fromimage(&mut self, img: DynamicImage, overlay_old: Option<Vec<Cell>>) {
-        self.add_history();
         // Loads an image into the self.board
 
         // The Option for passing an old overlay is there for internal processing, it allows us to skip resizing.
-        // We try not to make the canvas larger than 100 x 50 cells, so images above that will be scaled down.
-        // Amazingly, however, it doesn't crash on images even as large as 1920x1080, it's just super slow.
+        // Every pixel becomes a cell, so pictures should be scaled beforehand, loadimage does that.
 
-        let mut rgbimg = img.into_rgb8();
+        let rgbimg = img.into_rgb8();
         let (img_w, img_h) = rgbimg.dimensions() as (u32, u32);
+        let old_size = self.board.size;
         self.board = Board::new( Vec2::new(img_w as usize, (img_h / 2) as usize));
         let mut overlay_new: Vec<Cell>;
 
         match overlay_old {
             Some(o) => overlay_new = o,
 
-            None => {if img_w > 100 || img_h > 100 {
-                         rgbimg = DynamicImage::ImageRgb8(rgbimg).thumbnail(100, 50).into_rgb8();
-                         let (img_w, img_h) = rgbimg.dimensions() as (u32, u32);
-                         self.board = Board::new( Vec2::new(img_w as usize, (img_h / 2) as usize));
-                     }
-
-                     self.clear();  //For quickly resizing the overlay
+            None => {self.overlay = vec![self.blank(); self.board.size.x * self.board.size.y];  //For quickly resizing the overlay
+                     self.selection = None;
+                     self.floating = None;
+                     self.fit_layers(old_size);
 
-                     overlay_new = vec![Cell {color: Color::Rgb(255, 255, 255), backcolor: Color::Rgb(255, 255, 255), symbol: ' '}; self.board.size.x * self.board.size.y];
+                     overlay_new = vec![self.blank(); self.board.size.x * self.board.size.y];
                  },
         }
 
@@ -300,107 +686,674 @@ impl CanvasView {
             let y = (i / self.board.size.x) as u32;
 
             // Only every second line is parsed into the canvas to conserve image aspect ratio.
+            // Half blocks are the exception, they take both lines, as toimage wrote them.
             let rgb = rgbimg.get_pixel(x, 2*y);
-            overlay_new[i].backcolor = Color::Rgb(rgb[0], rgb[1], rgb[2]);
+            let top = Color::Rgb(rgb[0], rgb[1], rgb[2]);
+            let rgb = rgbimg.get_pixel(x, (2*y + 1).min(rgbimg.height() - 1));
+            let bottom = Color::Rgb(rgb[0], rgb[1], rgb[2]);
+
+            // Parts that can't be seen keep their colours, so filters leave empty cells on upper layers empty.
+            let cell = &mut overlay_new[i];
+            let (color, backcolor) = match cell.symbol {
+                '▀' => (Some(top), bottom),
+                '▄' => (Some(bottom), top),
+                _ => (None, top),
+            };
+            if let (Some(c), false) = (color, cell.no_color) {
+                cell.color = c;
+            }
+            if cell.alpha > 0 {
+                cell.backcolor = backcolor;
+            }
         }
 
         self.overlay = overlay_new;
+        self.add_history();
+    }
+
+
+    fn imageboard(img: DynamicImage, options: &import::ImageOptions, size: (Option<usize>, Option<usize>)) -> Board {
+        // A picture turned into cells in any of the ways import knows.
+        let mut rgbimg = import::scale(&img.into_rgb8(), options, size);
+        if let Some(colors) = &options.colors {
+            dither::quantize(&mut rgbimg, colors, options.dither);
+        }
+
+        match &options.mode {
+            import::ImageMode::Background => import::backgrounds(&rgbimg),
+            import::ImageMode::HalfBlocks => import::half_blocks(&rgbimg),
+            import::ImageMode::Ascii(options) => import::ascii(&rgbimg, options),
+            import::ImageMode::Braille(options) => import::braille(&rgbimg, options),
+        }
+    }
+
+    pub fn loadimage(&mut self, img: DynamicImage, options: &import::ImageOptions) {
+        // Replaces the canvas with a picture, scaled to fit the canvas as it was made unless options say otherwise.
+        let size = match (options.width, options.height) {
+            (None, None) => (Some(self.start.x), Some(self.start.y)),
+            size => size,
+        };
+
+        let board = CanvasView::imageboard(img, options, size);
+        self.fromboard(board);
     }
 
+    pub fn pasteimage(&mut self, img: DynamicImage, options: &import::ImageOptions, at: Vec2, part: Option<Part>) {
+        // Puts a picture over the canvas like a paste, at and up to the given size or else as much as fits from there on.
+        // Backgrounds go in as backgrounds, text art and braille as symbols and half blocks whole, unless part says otherwise.
+        if self.refuse() {
+            return;
+        }
+
+        let at = at.or_min(self.board.size.saturating_sub((1, 1)));
+        let size = match (options.width, options.height) {
+            (None, None) => (Some(self.board.size.x - at.x), Some(self.board.size.y - at.y)),
+            size => size,
+        };
+
+        let mut board = CanvasView::imageboard(img, options, size);
+        let part = part.unwrap_or(match options.mode {
+            import::ImageMode::Background => Part::Background,
+            import::ImageMode::HalfBlocks => Part::All,
+            _ => Part::Symbols,
+        });
+
+        // Backgrounds used as character colours.
+        if part == Part::Foreground && matches!(options.mode, import::ImageMode::Background) {
+            for cell in board.cells.iter_mut() {
+                cell.color = cell.backcolor;
+            }
+        }
+
+        self.commit_floating();
+        self.selection = None;
+        self.floating = Some(Floating {pos: at, board, origin: None, grab: None, part});
+        self.brush.borrow_mut().tool = Tool::Select;
+    }
 
     pub fn totext(&mut self) -> String {
         let mut text: String = String::from("");
 
-        for (i, cell) in self.overlay.iter().enumerate() {
+        let mut covered = false;
+        for (i, cell) in self.composite().iter().enumerate() {
             let x = i % self.board.size.x;
 
-            if x != self.board.size.x - 1 {
+            // A wide character already fills the blank cell after it.
+            if !(covered && cell.symbol == ' ') {
                 text.push(cell.symbol);
             }
+            covered = import::is_wide(cell.symbol);
 
-            else {
-                text.push(cell.symbol);
+            if x == self.board.size.x - 1 {
                 text.push('\n');
+                covered = false;
             }
         }
 
         text
     }
 
+    pub fn toansi(&mut self, options: export::AnsiOptions) -> String {
+        export::to_ansi(self.board.size, &self.composite(), options)
+    }
+
+    pub fn tohtml(&mut self, options: &export::HtmlOptions) -> String {
+        export::to_html(self.board.size, &self.composite(), options)
+    }
+
+    pub fn tosvg(&mut self, options: &export::SvgOptions) -> String {
+        export::to_svg(self.board.size, &self.composite(), options)
+    }
+
     pub fn tofile (&mut self) -> String {
-        let mut text: String = String::from("");
-        match self.overlay[0].backcolor {
-            Color::Rgb(_r, _g, _b) => {text = text + "RGB\n";}, // We might want an Alpha Channel in the future, so we better tell everyone what this is.
-            _ => {},
+        let layers: Vec<(&Layer, &[Cell])> = self.layers.iter().enumerate().map(|(i, l)| (l, self.layer_cells(i))).collect();
+        kkun::write(self.board.size, &layers, &self.meta)
+    }
+
+    pub fn fromfile (&mut self, text: &str) -> Result<(), kkun::ParseError> {
+        // Loads a kakikun project. If anything is wrong with it, the canvas stays as it is.
+        let project = kkun::read(text)?;
+
+        self.meta = project.meta;
+        let (layers, stack) = project.layers.into_iter().unzip();
+        self.set_layers(project.size, layers, stack, 0);
+        self.add_history();
+
+        Ok(())
+    }
+
+    pub fn fromansi(&mut self, bytes: &[u8]) -> Vec<String> {
+        // Loads text with colour escapes, the canvas takes on its size. Returns what couldn't be understood.
+        let (board, problems) = import::from_ansi(bytes);
+        self.fromboard(board);
+
+        problems
+    }
+
+    pub fn fromtext(&mut self, text: &str) {
+        // Ascii art and the like. The text takes the brush colours, unless those would make it invisible.
+        let brush = self.brush.borrow().cell();
+        let pen = if brush.color == brush.backcolor {
+            Cell {color: Color::Rgb(0, 0, 0), ..Cell::default()}
         }
+        else {
+            brush
+        };
 
-        text = text + &format!("{:0>4}x{:0>4}\n\t", self.board.size.x, self.board.size.y)[..]; // This formatting assumes maximum dimenstions of 9999x9999
+        let board = import::from_text(text, pen);
+        self.fromboard(board);
+    }
 
-        for (_i, cell) in self.overlay.iter().enumerate() {
-            let back_col = cell.backcolor;
-            let col = cell.color;
-            match (col, back_col) {
-                (Color::Rgb(r, g, b), Color::Rgb(r2, g2, b2)) => {
-                    text = text + &format!("{:0>3}{:0>3}{:0>3}|{:0>3}{:0>3}{:0>3}|{}\t", r,g,b,r2,g2,b2,cell.symbol)[..];
-                }
-                _ => continue
+    fn fromboard(&mut self, board: Board) {
+        // Replaces the whole canvas, size, layers and all.
+        self.set_layers(board.size, vec![Layer::new("Background")], vec![board.cells], 0);
+        self.add_history();
+    }
+
+    fn set_layers(&mut self, size: Vec2, layers: Vec<Layer>, mut stack: Vec<Vec<Cell>>, active: usize) {
+        self.board = Board::new(size);
+        self.active = active.min(layers.len() - 1);
+        self.overlay = std::mem::take(&mut stack[self.active]);
+        self.layers = layers;
+        self.stack = stack;
+        self.selection = None;
+        self.floating = None;
+    }
+
+    fn layer_cells(&self, i: usize) -> &[Cell] {
+        if i == self.active {&self.overlay} else {&self.stack[i]}
+    }
+
+    fn fit_layers(&mut self, old: Vec2) {
+        // After the active layer changed size, the others are cut off or filled up to match it.
+        let (size, active) = (self.board.size, self.active);
+        for (i, cells) in self.stack.iter_mut().enumerate().filter(|(i, _)| *i != active) {
+            let blank = if i == 0 {Cell::default()} else {Cell::CLEAR};
+            *cells = (0..size.x * size.y)
+                .map(|j| (j % size.x, j / size.x))
+                .map(|(x, y)| if x < old.x && y < old.y {cells[x + y * old.x]} else {blank})
+                .collect();
+        }
+    }
+
+    fn composite_with(&self, active: &[Cell], base: Cell) -> Vec<Cell> {
+        // Stacks the visible layers onto base, with the given cells standing in for the active layer.
+        let mut cells = vec![base; self.board.size.x * self.board.size.y];
+        for (i, layer) in self.layers.iter().enumerate().filter(|(_, l)| l.visible) {
+            let layer_cells = if i == self.active {active} else {&self.stack[i]};
+            for (below, above) in cells.iter_mut().zip(layer_cells.iter()) {
+                *below = stack(*below, *above, layer.opacity, layer.blend);
             }
         }
-        text
+
+        cells
+    }
+
+    pub fn composite(&self) -> Vec<Cell> {
+        // What the canvas looks like, on white where nothing covers it. This is what gets exported.
+        self.composite_with(&self.overlay, Cell::default())
+    }
+
+    pub fn get_layers(&self) -> &[Layer] {
+        &self.layers
+    }
+
+    pub fn get_active(&self) -> usize {
+        self.active
+    }
+
+    pub fn locked(&self) -> bool {
+        self.layers[self.active].locked
+    }
+
+    fn refuse(&mut self) -> bool {
+        // Everything that changes the active layer asks this first, a locked layer stays as it is.
+        if self.locked() {
+            self.refused = true;
+        }
+
+        self.locked()
     }
 
-    pub fn fromfile (&mut self, text: String) {
-        let lines: Vec<&str> = text.lines().collect();
+    pub fn take_refused(&mut self) -> bool {
+        // Whether something was refused since the last time, so the reason can be shown.
+        std::mem::take(&mut self.refused)
+    }
 
-        if lines[0] == "RGB" {
-            let (width, height) = (&lines[1][0..4], &lines[1][5..9]);
+    pub fn set_layer(&mut self, layer: Layer) {
+        // Renames, hides, locks or fades the active layer.
+        self.layers[self.active] = layer;
+        self.add_history();
+    }
 
-            self.board = Board::new( Vec2::new(width.parse::<usize>().unwrap(), height.parse::<usize>().unwrap()));
-            self.clear();
-            let mut overlay_new = vec![Cell {color: Color::Rgb(255, 255, 255), backcolor: Color::Rgb(255, 255, 255), symbol: ' '} ; self.board.size.x * self.board.size.y];
+    pub fn select_layer(&mut self, i: usize) -> bool {
+        if i >= self.layers.len() {
+            return false;
+        }
 
-            let cs: Vec<&str> = lines[2].split('\t').collect();
+        self.commit_floating();
+        self.stack[self.active] = std::mem::take(&mut self.overlay);
+        self.overlay = std::mem::take(&mut self.stack[i]);
+        self.active = i;
 
-            for (i, _cell) in self.overlay.iter().enumerate() {
-                let c: Vec<char> = cs[i+1].to_string().chars().collect();
-                // the following is written so badly it's almost comical, sorry. Iterating over unicode strings is so hard :(
-                let (r, g, b) = (c[0..3].iter().collect::<String>().parse::<u8>().unwrap(), c[3..6].iter().collect::<String>().parse::<u8>().unwrap(), c[6..9].iter().collect::<String>().parse::<u8>().unwrap());
-                let (r2,g2,b2) = (c[10..13].iter().collect::<String>().parse::<u8>().unwrap(), c[13..16].iter().collect::<String>().parse::<u8>().unwrap(), c[16..19].iter().collect::<String>().parse::<u8>().unwrap());
-                let s = c[20].to_string().parse::<char>().unwrap_or(' ');
+        true
+    }
 
+    pub fn add_layer(&mut self, name: &str) {
+        // New layers are see-through and go right above the active one.
+        self.commit_floating();
+        let i = self.active + 1;
+        self.layers.insert(i, Layer::new(name));
+        self.stack.insert(i, vec![Cell::CLEAR; self.board.size.x * self.board.size.y]);
+        self.select_layer(i);
+        self.add_history();
+    }
 
+    pub fn delete_layer(&mut self) -> bool {
+        // The last layer can't go, and neither can a locked one.
+        if self.refuse() || self.layers.len() == 1 {
+            return false;
+        }
+
+        self.floating = None;
+        self.layers.remove(self.active);
+        self.stack.remove(self.active);
+        self.active = self.active.saturating_sub(1);
+        self.overlay = std::mem::take(&mut self.stack[self.active]);
+        self.add_history();
 
-                overlay_new[i] = Cell {color: Color::Rgb(r, g, b), backcolor: Color::Rgb(r2,g2,b2), symbol: s};
+        true
+    }
+
+    pub fn move_layer(&mut self, up: bool) -> bool {
+        // Swaps the active layer with the one above or below it.
+        let other = match up {
+            true if self.active + 1 < self.layers.len() => self.active + 1,
+            false if self.active > 0 => self.active - 1,
+            _ => return false,
+        };
+
+        self.commit_floating();
+        self.layers.swap(self.active, other);
+        self.stack.swap(self.active, other);
+        self.active = other;
+        self.add_history();
+
+        true
+    }
+
+    pub fn merge_layer(&mut self) -> bool {
+        // Flattens the active layer into the one below it, as it looks now. A hidden layer simply disappears.
+        if self.active == 0 || self.locked() || self.layers[self.active - 1].locked {
+            return false;
+        }
+
+        self.commit_floating();
+        let layer = self.layers.remove(self.active);
+        let cells = std::mem::take(&mut self.overlay);
+        self.stack.remove(self.active);
+        self.active -= 1;
+        self.overlay = std::mem::take(&mut self.stack[self.active]);
+
+        if layer.visible {
+            for (below, above) in self.overlay.iter_mut().zip(cells.iter()) {
+                *below = stack(*below, *above, layer.opacity, layer.blend);
             }
-            self.overlay = overlay_new;
+        }
+        self.add_history();
+
+        true
+    }
+
+    pub fn set_meta(&mut self, key: &str, value: &str) -> bool {
+        // Metadata like the author is kept in .kkun files, except under the keys the format needs for itself.
+        if kkun::reserved(key) {
+            return false;
+        }
 
+        self.meta.retain(|(k, _)| k != key);
+        if !value.is_empty() {
+            self.meta.push((key.to_string(), value.to_string()));
         }
+
+        true
+    }
+
+    pub fn get_meta(&self) -> &[(String, String)] {
+        &self.meta
     }
 
     pub fn back(&mut self) {
-        let overlay_past = self.history_o.pop();
+        // Anything that wasn't recorded yet is recorded first, so it can be redone.
+        self.add_history();
 
-        match overlay_past {
-            Some(o) => {self.overlay = o;
-                        self.board = self.history_b.pop().unwrap()},
-            None => {}
+        if let Some((board, layers)) = self.history.undo() {
+            self.restore(board, layers);
         }
     }
 
-    pub fn add_history(&mut self) {
-        if self.history_o.len() > 250 { // We could decide on a different history length, but this works for me.
-            self.history_o.remove(0);
-            self.history_b.remove(0);
+    pub fn forward(&mut self) {
+        // Redoes what back undid, as long as nothing new was painted in between.
+        if let Some((board, layers)) = self.history.redo() {
+            self.restore(board, layers);
         }
+    }
 
-        let overlay = self.get_overlay();
-        let board = self.get_board();
-        self.history_o.push(overlay);
-        self.history_b.push(board);
+    fn restore(&mut self, board: Board, layers: Vec<Layer>) {
+        // History keeps the layers one under the other, as one tall board.
+        let size = Vec2::new(board.size.x, board.size.y / layers.len());
+        let stack = board.cells.chunks(size.x * size.y).map(|c| c.to_vec()).collect();
+        self.set_layers(size, layers, stack, self.active);
+    }
+
+    pub fn add_history(&mut self) {
+        // Remembers the current state as one step, to be called after every change to the canvas.
+        let n = self.layers.len();
+        let cells: Vec<Cell> = (0..n).flat_map(|i| self.layer_cells(i).iter().copied()).collect();
+        self.history.record(Vec2::new(self.board.size.x, self.board.size.y * n), &cells, &self.layers);
+    }
+
+    pub fn get_history(&mut self) -> &mut History {
+        &mut self.history
     }
 
     pub fn set_tool(&mut self, tool: Tool) {
-        self.tool = tool;
+        self.commit_floating();
+        self.brush.borrow_mut().tool = tool;
+        self.anchor = None;
+        self.hover = None;
+    }
+
+    fn region(&self, min: Vec2, max: Vec2) -> Board {
+        // Copies the cells between two corners into a board of their own.
+        let size = max - min + (1, 1);
+        let cells = (min.y..=max.y)
+            .flat_map(|y| (min.x..=max.x).map(move |x| x + y * self.board.size.x))
+            .map(|i| self.overlay[i])
+            .collect();
+
+        Board {size, cells}
+    }
+
+    fn clear_region(&mut self, min: Vec2, max: Vec2) {
+        for y in min.y..=max.y {
+            for x in min.x..=max.x {
+                self.overlay[x + y * self.board.size.x] = self.blank();
+            }
+        }
+    }
+
+    fn selected(&self) -> Option<(Vec2, Vec2)> {
+        // The selection, including one that is just being dragged open.
+        match (self.tool(), self.anchor, self.focused) {
+            (Tool::Select, Some(a), Some(b)) => Some((a.or_min(b), a.or_max(b))),
+            _ => self.selection,
+        }
+    }
+
+    pub fn copy_selection(&mut self) -> bool {
+        if let Some(f) = &self.floating {
+            self.clipboard = Some(f.board.clone());
+        }
+        else if let Some((min, max)) = self.selection {
+            self.clipboard = Some(self.region(min, max));
+        }
+        else {
+            return false;
+        }
+
+        true
+    }
+
+    pub fn cut_selection(&mut self) -> bool {
+        !self.refuse() && self.copy_selection() && self.delete_selection()
+    }
+
+    pub fn delete_selection(&mut self) -> bool {
+        // Resets the selected cells to the blank background. A floating paste is simply dropped.
+        if self.refuse() {
+            return false;
+        }
+
+        if let Some(f) = self.floating.take() {
+            if let Some((min, max)) = f.origin {
+                self.clear_region(min, max);
+                self.add_history();
+            }
+            self.selection = None;
+        }
+        else if let Some((min, max)) = self.selection {
+            self.clear_region(min, max);
+            self.add_history();
+        }
+        else {
+            return false;
+        }
+
+        true
+    }
+
+    pub fn paste(&mut self) -> bool {
+        // The clipboard hovers over the canvas until it's put down, either by clicking elsewhere or by deselecting.
+        if self.refuse() {
+            return false;
+        }
+
+        let board = match &self.clipboard {
+            Some(b) => b.clone(),
+            None => return false,
+        };
+
+        self.commit_floating();
+        let pos = self.selection.map(|(min, _)| min).unwrap_or_else(Vec2::zero);
+        self.selection = None;
+        self.floating = Some(Floating {pos, board, origin: None, grab: None, part: Part::All});
+        self.brush.borrow_mut().tool = Tool::Select;
+
+        true
+    }
+
+    pub fn deselect(&mut self) {
+        self.commit_floating();
+        self.selection = None;
+    }
+
+    fn lift_selection(&mut self, grab: Vec2) {
+        if let Some((min, max)) = self.selection.take() {
+            let board = self.region(min, max);
+            self.floating = Some(Floating {pos: min, board, origin: Some((min, max)), grab: Some(grab - min), part: Part::All});
+        }
+    }
+
+    fn commit_floating(&mut self) {
+        // Puts a floating block down on the canvas, anything hanging over the edge is cut off.
+        // A layer that was locked in the meantime doesn't take it, the block is dropped and the canvas stays as it was.
+        let f = match self.floating.take() {
+            Some(_) if self.refuse() => return,
+            Some(f) => f,
+            None => return,
+        };
+
+        if let Some((min, max)) = f.origin {
+            self.clear_region(min, max);
+        }
+
+        for (i, cell) in f.board.cells.iter().enumerate() {
+            let pos = f.pos + (i % f.board.size.x, i / f.board.size.x);
+            if let Some(j) = self.board.cell_id(pos) {
+                self.overlay[j] = f.part.over(self.overlay[j], *cell);
+            }
+        }
+
+        let max = (f.pos + f.board.size - (1, 1)).or_min(self.board.size.saturating_sub((1, 1)));
+        self.selection = Some((f.pos, max));
+        self.add_history();
+    }
+
+    fn is_brush(&self) -> bool {
+        // Tools that paint along the path of the mouse.
+        matches!(self.tool(), Tool::Draw | Tool::Braille | Tool::Erase)
+    }
+
+    fn footprint(&self, pos: Vec2) -> Vec<Vec2> {
+        // The cells the brush covers when it's centred on pos, cut off at the edges of the canvas.
+        let brush = *self.brush.borrow();
+        let size = brush.size.max(1) as isize;
+        let half = (size - 1) / 2;
+
+        let offsets: Vec<(isize, isize)> = match (brush.shape, &self.stamp) {
+            (Shape::Stamp, Some(stamp)) => {
+                // Only where the stamp has something in it.
+                let (w, h) = (stamp.size.x as isize, stamp.size.y as isize);
+                (0..w * h)
+                    .filter(|i| {let c = stamp.cells[*i as usize]; c.alpha > 0 || !c.no_symbol})
+                    .map(|i| (i % w - (w - 1) / 2, i / w - (h - 1) / 2))
+                    .collect()
+            }
+            (Shape::Circle, _) => {
+                // Cells are about twice as high as wide, so the circle gets half as many rows as columns, rounded.
+                // Like the columns, an even number of rows has pos just above the middle.
+                let rows = (size + 1) / 2;
+                let (rx, ry) = (size as f32 / 2., rows as f32 / 2.);
+                (0..size * rows)
+                    .map(|i| (i % size, i / size))
+                    .filter(|(x, y)| {
+                        let dx = *x as f32 - (size - 1) as f32 / 2.;
+                        let dy = *y as f32 - (rows - 1) as f32 / 2.;
+                        (dx / rx).powi(2) + (dy / ry).powi(2) <= 1.
+                    })
+                    .map(|(x, y)| (x - half, y - (rows - 1) / 2))
+                    .collect()
+            }
+            (Shape::Line, _) => (0..size).map(|x| (x - half, 0)).collect(),
+            _ => (0..size * size).map(|i| (i % size - half, i / size - half)).collect(),
+        };
+
+        offsets.into_iter()
+            .map(|(dx, dy)| (pos.x as isize + dx, pos.y as isize + dy))
+            .filter(|(x, y)| *x >= 0 && *y >= 0 && (*x as usize) < self.board.size.x && (*y as usize) < self.board.size.y)
+            .map(|(x, y)| Vec2::new(x as usize, y as usize))
+            .collect()
+    }
+
+    fn dab(&mut self, pos: Vec2) {
+        // One touch of the brush: the stamp, or the brush's cell on everything its shape covers, wherever this stroke hasn't painted yet.
+        let brush = *self.brush.borrow();
+        if let (Shape::Stamp, Some(stamp)) = (brush.shape, &self.stamp) {
+            let (w, h) = (stamp.size.x, stamp.size.y);
+            for (i, cell) in stamp.cells.iter().enumerate() {
+                let p = (pos + (i % w, i / w)).checked_sub(((w - 1) / 2, (h - 1) / 2));
+                if let Some(j) = p.and_then(|p| self.board.cell_id(p)) {
+                    // Where the stamp has nothing in it, the cell stays free for the next dab.
+                    if !self.touched.get(j).copied().unwrap_or(false) && (cell.alpha > 0 || !cell.no_symbol) {
+                        self.overlay[j] = Part::All.over(self.overlay[j], *cell);
+                        if let Some(t) = self.touched.get_mut(j) {
+                            *t = true;
+                        }
+                    }
+                }
+            }
+            return;
+        }
+
+        for p in self.footprint(pos) {
+            let i = p.x + p.y * self.board.size.x;
+            if !self.touched.get(i).copied().unwrap_or(false) {
+                self.changecolor(p, brush.color, brush.backcolor);
+                if let Some(t) = self.touched.get_mut(i) {
+                    *t = true;
+                }
+            }
+        }
+    }
+
+    pub fn set_stamp(&mut self) -> bool {
+        // The brush can paint with a block of cells: what floats, what's selected or else what was copied.
+        let stamp = match (&self.floating, self.selection, &self.clipboard) {
+            (Some(f), _, _) => f.board.clone(),
+            (None, Some((min, max)), _) => self.region(min, max),
+            (None, None, Some(c)) => c.clone(),
+            _ => return false,
+        };
+
+        self.stamp = Some(stamp);
+        true
+    }
+
+    fn erase(&mut self, pos: Vec2) {
+        // Back to white on the bottom layer, to nothing at all on the ones above.
+        let (part, blank) = (self.brush.borrow().erase, self.blank());
+        for p in self.footprint(pos) {
+            let i = p.x + p.y * self.board.size.x;
+            self.overlay[i] = part.erase(self.overlay[i], blank);
+        }
+    }
+
+    fn dot(&mut self, pos: Vec2, adding: bool) {
+        // Sets or clears the brush's braille dots in one cell. Anything that isn't braille counts as no dots yet.
+        let brush = *self.brush.borrow();
+        if let Some(i) = self.board.cell_id(pos) {
+            let cell = &mut self.overlay[i];
+            let old = match cell.symbol as u32 {
+                n @ 0x2800..=0x28ff if !cell.no_symbol => n - 0x2800,
+                _ => 0,
+            };
+            let new = if adding {old | brush.dots as u32} else {old & !(brush.dots as u32)};
+
+            if new != 0 || old != 0 {
+                cell.symbol = if new == 0 {' '} else {char::from_u32(0x2800 + new).unwrap_or(' ')};
+                cell.no_symbol = false;
+            }
+            if adding {
+                cell.color = brush.color;
+                cell.no_color = false;
+            }
+        }
+    }
+
+    fn stroke(&mut self, pos: Vec2) {
+        // Paints every cell between the last painted one and pos, so fast strokes don't come out dotted.
+        // The last painted one isn't painted again, blend modes would otherwise apply twice there.
+        let from = self.focused.unwrap_or(pos);
+        let brush = *self.brush.borrow();
+        for p in shapes::line(from, pos).into_iter().skip(self.focused.is_some() as usize) {
+            match brush.tool {
+                Tool::Braille => self.dot(p, self.adding),
+                _ if self.erasing => self.erase(p),
+                _ => self.dab(p),
+            }
+        }
+        self.focused = Some(pos);
+        self.hover = Some(pos);
+    }
+
+    fn shape_cells(&self) -> Vec<Vec2> {
+        // The cells covered by the shape that is currently being dragged, if any.
+        let (a, b) = match (self.anchor, self.focused) {
+            (Some(a), Some(b)) => (a, b),
+            _ => return Vec::new(),
+        };
+
+        match self.tool() {
+            Tool::Line => shapes::line(a, b),
+            Tool::Rectangle => shapes::rectangle(a, b, false),
+            Tool::FilledRectangle => shapes::rectangle(a, b, true),
+            Tool::Ellipse => shapes::ellipse(a, b, false),
+            Tool::FilledEllipse => shapes::ellipse(a, b, true),
+            _ => Vec::new(),
+        }
+    }
+
+    pub fn set_fill(&mut self, fill: FillOptions) {
+        self.fill = fill;
+    }
+
+    pub fn get_fill(&self) -> FillOptions {
+        self.fill
+    }
+
+    pub fn get_selection(&self) -> Option<(Vec2, Vec2)> {
+        self.selection
     }
 
     fn get_cell(&self, mouse_pos: Vec2, offset: Vec2) -> Option<Vec2> {
@@ -422,24 +1375,44 @@ impl CanvasView {
         self.board.size.y as u32
     }
     pub fn get_overlay(&mut self) -> Vec<Cell> {
-        let mut copy = vec![Cell {color: Color::Rgb(255, 255, 255), backcolor: Color::Rgb(255, 255, 255), symbol: ' '} ; self.board.size.x * self.board.size.y];
-        for (i, cell) in self.overlay.iter().enumerate() {copy[i] = *cell}
-        copy
+        self.overlay.clone()
     }
     pub fn get_overlay_len(&mut self) -> u32 {
         self.overlay.len() as u32
     }
-    pub fn get_board(&mut self) -> Board {
-        Board::new(self.board.size)
-    }
 }
 
 impl cursive::view::View for CanvasView {
     fn draw(&self, printer: &Printer) {
-        for (i, cell) in self.overlay.iter().enumerate() {
+        // A floating block is shown as if it was already put down on the active layer, then all layers are stacked.
+        let mut active = self.overlay.clone();
+        if let Some(f) = &self.floating {
+            if let Some((min, max)) = f.origin {
+                for y in min.y..=max.y {
+                    for x in min.x..=max.x {
+                        active[x + y * self.board.size.x] = self.blank();
+                    }
+                }
+            }
+
+            for (i, cell) in f.board.cells.iter().enumerate() {
+                let pos = f.pos + (i % f.board.size.x, i / f.board.size.x);
+                if let Some(j) = self.board.cell_id(pos) {
+                    active[j] = f.part.over(active[j], *cell);
+                }
+            }
+        }
+        let cells = self.composite_with(&active, Cell::default());
+
+        for (i, cell) in cells.iter().enumerate() {
             let x = i % self.board.size.x;
             let y = i / self.board.size.x;
 
+            // Printing the blank after a wide character would cut that character in half.
+            if x > 0 && cell.symbol == ' ' && import::is_wide(cells[i - 1].symbol) {
+                continue;
+            }
+
             let text = cell.symbol;
             let backcolor = cell.backcolor;
             let color = cell.color;
@@ -449,6 +1422,45 @@ impl cursive::view::View for CanvasView {
                 |printer| printer.print((x, y), &text.to_string()),
             );
         }
+
+        // The border of the selection is shown with swapped colours.
+        let marked = match (&self.floating, self.selected()) {
+            (Some(f), _) => Some((f.pos, f.pos + f.board.size - (1, 1))),
+            (None, s) => s,
+        };
+        if let Some((min, max)) = marked {
+            for pos in shapes::rectangle(min, max, false) {
+                if let Some(i) = self.board.cell_id(pos) {
+                    let cell = cells[i];
+                    printer.with_color(
+                        ColorStyle::new(cell.backcolor, cell.color),
+                        |printer| printer.print(pos, &cell.symbol.to_string()),
+                    );
+                }
+            }
+        }
+
+        // A shape that is still being dragged is only previewed on top, it's painted for real on release.
+        let brush = *self.brush.borrow();
+        for pos in self.shape_cells() {
+            printer.with_color(
+                ColorStyle::new(brush.color, brush.backcolor),
+                |printer| printer.print(pos, &brush.symbol.to_string()),
+            );
+        }
+
+        // Brushes bigger than a cell show what they cover with swapped colours. The terminal only tells us about the mouse
+        // while a button is pressed, so this is only there for the length of a stroke.
+        if let (Some(pos), Tool::Draw | Tool::Erase) = (self.hover, brush.tool) {
+            let covered = self.footprint(pos);
+            for p in covered.iter().filter(|_| covered.len() > 1) {
+                let cell = cells[p.x + p.y * self.board.size.x];
+                printer.with_color(
+                    ColorStyle::new(cell.backcolor, cell.color),
+                    |printer| printer.print(*p, &cell.symbol.to_string()),
+                );
+            }
+        }
     }
 
     fn take_focus(&mut self, _: Direction) -> Result<EventResult, CannotFocus> {
@@ -456,27 +1468,90 @@ impl cursive::view::View for CanvasView {
     }
 
     fn on_event(&mut self, event: Event) -> EventResult {
+        // A locked layer can still be picked from and selected on, but not painted on.
+        if self.locked() && !matches!(self.tool(), Tool::Pipette | Tool::Select) {
+            if let Event::Mouse {event: MouseEvent::Press(_), ..} = event {
+                self.refuse();
+                return EventResult::with_cb(crate::refused_feedback);
+            }
+            return EventResult::Ignored;
+        }
+
         match event {
             Event::Mouse {
                 offset,
                 position,
-                event: MouseEvent::Press(_btn),
+                event: MouseEvent::Press(btn),
             } => {
-                match self.tool {
+                match self.tool() {
                     Tool::Pipette => {
                         if let Some(pos) = self.get_cell(position, offset) {
                             let i = pos.x + self.get_width() as usize * pos.y;
-                            let cell = self.overlay[i];
-                            unsafe {
-                                BRUSHCOLOR = cell.color;
-                                BRUSHBACKCOLOR = cell.backcolor;
-                            }
+                            let cell = self.composite()[i];
+                            let mut brush = self.brush.borrow_mut();
 
-                            self.brushsymbol = cell.symbol;
-                            self.tool = Tool::Draw;
+                            brush.color = cell.color;
+                            brush.backcolor = cell.backcolor;
+                            brush.symbol = cell.symbol;
+                            brush.tool = Tool::Draw;
                     }},
 
-                    _ => {}
+                    Tool::Fill => {
+                        if let Some(pos) = self.get_cell(position, offset) {
+                            self.flood_fill(pos);
+                            return EventResult::Consumed(None);
+                        }
+                    },
+
+                    Tool::Draw | Tool::Erase => {
+                        // The right mouse button erases while drawing.
+                        if let Some(pos) = self.get_cell(position, offset) {
+                            self.erasing = matches!(self.tool(), Tool::Erase) || btn == MouseButton::Right;
+                            self.touched = vec![false; self.overlay.len()];
+                            self.focused = None;
+                            self.stroke(pos);
+                            return EventResult::Consumed(None);
+                        }
+                    },
+
+                    Tool::Braille => {
+                        // The first cell decides: if it has all of the brush's dots already, this stroke takes them away.
+                        if let Some(pos) = self.get_cell(position, offset) {
+                            let dots = self.brush.borrow().dots as u32;
+                            let old = self.overlay[pos.x + self.board.size.x * pos.y].symbol as u32;
+                            self.adding = !(0x2800..=0x28ff).contains(&old) || (old - 0x2800) & dots != dots;
+                            self.focused = None;
+                            self.stroke(pos);
+                            return EventResult::Consumed(None);
+                        }
+                    },
+
+                    Tool::Select => {
+                        if let Some(pos) = self.get_cell(position, offset) {
+                            match (&mut self.floating, self.selection) {
+                                (Some(f), _) if f.contains(pos) => f.grab = Some(pos - f.pos),
+                                (None, Some((min, max))) if pos.fits(min) && pos.fits_in(max) => self.lift_selection(pos),
+                                _ => {
+                                    self.commit_floating();
+                                    self.selection = None;
+                                    self.anchor = Some(pos);
+                                    self.focused = Some(pos);
+                                }
+                            }
+                            if self.refused {
+                                return EventResult::with_cb(crate::refused_feedback);
+                            }
+                            return EventResult::Consumed(None);
+                        }
+                    },
+
+                    Tool::Line | Tool::Rectangle | Tool::FilledRectangle | Tool::Ellipse | Tool::FilledEllipse => {
+                        if let Some(pos) = self.get_cell(position, offset) {
+                            self.anchor = Some(pos);
+                            self.focused = Some(pos);
+                            return EventResult::Consumed(None);
+                        }
+                    },
                 }
             },
 
@@ -487,18 +1562,47 @@ impl cursive::view::View for CanvasView {
             } => {
                 // Get cell for position
                 if let Some(pos) = self.get_cell(position, offset) {
-                    self.focused = Some(pos);
-                    unsafe {
-                        self.changecolor(pos, BRUSHCOLOR, BRUSHBACKCOLOR);
+                    if self.is_brush() {
+                        self.stroke(pos);
+                    }
+                    else if let Some(Floating {pos: p, grab: Some(grab), ..}) = &mut self.floating {
+                        *p = pos.saturating_sub(*grab);
+                    }
+                    else {
+                        self.focused = Some(pos);
                     }
 
                     return EventResult::Consumed(None);
                 }
             },
 
+            Event::Mouse {
+                event: MouseEvent::Release(_btn), ..
+            } if matches!(self.tool(), Tool::Select) => {
+                if let Some(f) = &mut self.floating {
+                    f.grab = None;
+                }
+                else if self.anchor.is_some() {
+                    self.selection = self.selected();
+                }
+                self.anchor = None;
+                self.focused = None;
+            },
+
             Event::Mouse {
                 event: MouseEvent::Release(_btn), ..
             } => {
+                // Every cell only once, so blend modes don't apply twice where the outline meets itself.
+                let brush = *self.brush.borrow();
+                let mut cells = self.shape_cells();
+                cells.sort_by_key(|p| (p.y, p.x));
+                cells.dedup();
+                for pos in cells {
+                    self.changecolor(pos, brush.color, brush.backcolor);
+                }
+                self.anchor = None;
+                self.focused = None;
+                self.hover = None;
                 self.add_history();
             }
             _ => (),
@@ -518,15 +1622,19 @@ pub struct PickView {
     // Hue is a number from 0 to 360, maybe imagine degrees around the colour wheel.
     // Width and Height are the width and height on the screen in cells the picker takes up.
 
+    // The bottom row below the colours shows what the brush currently paints with.
+
     hue: u16,
     pub width:u8,
     pub height: u8,
+    brush: SharedBrush,
 }
 
 impl PickView {
-    pub fn new(width: u8, height: u8) -> Self {
+    #[allow(clippy::unnecessary_cast)]
+    pub fn new(width: u8, height: u8, brush: SharedBrush) -> Self {
         let hue = 0 as u16;
-        PickView {hue, width, height}
+        PickView {hue, width, height, brush}
     }
 
 
@@ -551,6 +1659,7 @@ impl PickView {
 impl cursive::view::View for PickView {
     //  Here we implement Cursive's methods for our Colour Picker.
 
+    #[allow(clippy::needless_ifs)]
     fn draw(&self, printer: &Printer) {
         let rect = self.width * self.height;
 
@@ -572,12 +1681,27 @@ impl cursive::view::View for PickView {
                 |printer| printer.print((x, y), " "),
             );
         }
+
+        // Left half the background colour with the brush symbol on it, right half the character's colour on its own.
+        let brush = self.brush.borrow();
+        for x in 0..self.width {
+            let symbol = match brush.tool {
+                Tool::Braille => char::from_u32(0x2800 + brush.dots as u32).unwrap_or(' '),
+                _ => brush.symbol,
+            };
+            let text = if x < self.width / 2 {symbol} else {'█'};
+            printer.with_color(
+                ColorStyle::new(brush.color, brush.backcolor),
+                |printer| printer.print((x, self.height), &text.to_string()),
+            );
+        }
     }
 
     fn take_focus(&mut self, _: Direction) -> Result<EventResult, CannotFocus> {
         Ok(EventResult::Consumed(None))
     }
 
+    #[allow(clippy::single_match)]
     fn on_event(&mut self, event: Event) -> EventResult {
         match event {
             Event::Mouse {
@@ -588,26 +1712,22 @@ impl cursive::view::View for PickView {
                     match btn {
                         MouseButton::Left => {
                             if let Some(pos) = self.get_cell(position, offset) {
-                                unsafe {
-                                    let value = 1. - (pos.y as f32 / self.height as f32);
-                                    let saturation = pos.x as f32 / self.width as f32;
-                                    let rgb = hsv_to_rbg(self.hue, saturation, value);
+                                let value = 1. - (pos.y as f32 / self.height as f32);
+                                let saturation = pos.x as f32 / self.width as f32;
+                                let rgb = hsv_to_rbg(self.hue, saturation, value);
 
-                                    BRUSHBACKCOLOR = Color::Rgb(rgb.0, rgb.1, rgb.2);
-                                }
+                                self.brush.borrow_mut().backcolor = Color::Rgb(rgb.0, rgb.1, rgb.2);
 
                                 return EventResult::Consumed(None);
                             }
                         }
                         MouseButton::Right => {
                             if let Some(pos) = self.get_cell(position, offset) {
-                                unsafe {
-                                    let value = 1. - (pos.y as f32 / self.height as f32);
-                                    let saturation = pos.x as f32 / self.width as f32;
-                                    let rgb = hsv_to_rbg(self.hue, saturation, value);
+                                let value = 1. - (pos.y as f32 / self.height as f32);
+                                let saturation = pos.x as f32 / self.width as f32;
+                                let rgb = hsv_to_rbg(self.hue, saturation, value);
 
-                                    BRUSHCOLOR = Color::Rgb(rgb.0, rgb.1, rgb.2);
-                                }
+                                self.brush.borrow_mut().color = Color::Rgb(rgb.0, rgb.1, rgb.2);
 
                                 return EventResult::Consumed(None);
                             }
@@ -623,7 +1743,142 @@ impl cursive::view::View for PickView {
     }
 
     fn required_size(&mut self, _: Vec2) -> Vec2 {
-        let vector = Vec2::new(self.width.into(), self.height.into());
+        let vector = Vec2::new(self.width.into(), self.height as usize + 1);
         vector.map_x(|x| x)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn symbols_blend_with_the_background_of_blanks() {
+        // A blank painted with the default brush has a black, invisible foreground.
+        let below = Cell {color: Color::Rgb(0, 0, 0), backcolor: Color::Rgb(200, 100, 50), ..Cell::default()};
+        let above = Cell {symbol: 'x', color: Color::Rgb(255, 0, 0), alpha: 0, ..Cell::default()};
+
+        let cell = stack(below, above, 100, Blend::Multiply);
+        assert!(cell.symbol == 'x' && cell.color == Color::Rgb(200, 0, 0));
+
+        // Over a visible symbol its colour is what counts.
+        let below = Cell {symbol: 'o', ..below};
+        assert!(stack(below, above, 100, Blend::Multiply).color == Color::Rgb(0, 0, 0));
+    }
+
+    fn canvas(size: Vec2) -> (CanvasView, SharedBrush) {
+        let brush = crate::brush::Brush::shared();
+        (CanvasView::new(size, brush.clone()), brush)
+    }
+
+    #[test]
+    fn circles_are_round_on_screen() {
+        let (view, brush) = canvas(Vec2::new(30, 20));
+        brush.borrow_mut().shape = Shape::Circle;
+        let extent = |size: usize| {
+            brush.borrow_mut().size = size;
+            let covered = view.footprint(Vec2::new(10, 10));
+            let (xs, ys): (Vec<usize>, Vec<usize>) = covered.iter().map(|p| (p.x, p.y)).unzip();
+            (xs.iter().max().unwrap() - xs.iter().min().unwrap() + 1, ys.iter().max().unwrap() - ys.iter().min().unwrap() + 1, covered.len())
+        };
+
+        assert_eq!(extent(1), (1, 1, 1));
+        assert_eq!(extent(3), (3, 2, 6));
+        assert_eq!(extent(4), (4, 2, 8));
+        assert_eq!(extent(5), (5, 3, 11));
+        let (w, h, _) = extent(20);
+        assert_eq!((w, h), (20, 10));
+    }
+
+    #[test]
+    fn locked_layers_refuse_paint_and_deletion() {
+        let (mut view, _) = canvas(Vec2::new(4, 4));
+        view.add_layer("ink");
+        view.set_layer(Layer {locked: true, ..Layer::new("ink")});
+        let before = view.overlay.clone();
+
+        let press = Event::Mouse {offset: Vec2::zero(), position: Vec2::new(1, 1), event: MouseEvent::Press(MouseButton::Left)};
+        assert!(matches!(cursive::View::on_event(&mut view, press), EventResult::Consumed(Some(_))));
+        assert!(view.take_refused() && view.overlay == before);
+
+        assert!(!view.delete_layer() && view.take_refused());
+        assert_eq!(view.layers.len(), 2);
+    }
+
+    #[test]
+    fn filters_leave_see_through_cells_out() {
+        let (mut view, _) = canvas(Vec2::new(6, 3));
+        view.add_layer("sketch");
+        let ink = Cell {backcolor: Color::Rgb(0, 0, 0), ..Cell::default()};
+        for x in 2..4 {
+            view.overlay[x + 6] = ink;
+        }
+
+        view.blur(1.0);
+        assert!(view.overlay[2 + 6] == ink && view.overlay[3 + 6] == ink);
+        assert!(view.overlay.iter().filter(|c| c.alpha == 0).all(|c| *c == Cell::CLEAR));
+
+        view.overlay[2 + 6].backcolor = Color::Rgb(100, 100, 100);
+        view.dither(&[(0, 0, 0), (255, 255, 255)], dither::Dither::FloydSteinberg);
+        assert!(view.overlay[2 + 6] == ink && view.overlay[3 + 6] == ink);
+        assert!(view.overlay.iter().filter(|c| c.alpha == 0).all(|c| *c == Cell::CLEAR));
+    }
+
+    #[test]
+    fn flips_move_whole_cells() {
+        let (mut view, _) = canvas(Vec2::new(4, 2));
+        view.add_layer("sketch");
+        let ink = Cell {backcolor: Color::Rgb(0, 0, 0), symbol: '▀', ..Cell::default()};
+        view.overlay[0] = ink;
+
+        view.fliph();
+        assert!(view.overlay[3] == ink && view.overlay[0] == Cell::CLEAR);
+        view.flipv();
+        assert!(view.overlay[7] == Cell {symbol: '▄', ..ink});
+        assert_eq!(view.overlay.iter().filter(|c| **c == Cell::CLEAR).count(), 7);
+    }
+
+    #[test]
+    fn rotating_turns_every_layer() {
+        let (mut view, _) = canvas(Vec2::new(4, 2));
+        view.overlay[0].backcolor = Color::Rgb(255, 0, 0);
+        view.add_layer("sketch");
+        view.overlay[4] = Cell {backcolor: Color::Rgb(0, 0, 255), alpha: 128, ..Cell::CLEAR};
+
+        view.rotate90();
+        assert_eq!(view.board.size, Vec2::new(4, 2));
+        // Turned clockwise, a cell lies on its side as two cells half as high. The top left one ends up at the top right,
+        // the one below it at the top left.
+        let (bottom, top) = (view.layer_cells(0).to_vec(), view.layer_cells(1).to_vec());
+        let red: Vec<usize> = (0..8).filter(|i| bottom[*i].backcolor == Color::Rgb(255, 0, 0)).collect();
+        assert_eq!(red, vec![2, 3]);
+        assert!(top[0].backcolor == Color::Rgb(0, 0, 255) && top[0].alpha == 128 && top[1] == top[0]);
+        assert!(top[2..].iter().all(|c| *c == Cell::CLEAR));
+        assert!(view.composite()[3].backcolor == Color::Rgb(255, 0, 0));
+
+        let mut locked = view.get_layers()[0].clone();
+        locked.locked = true;
+        view.select_layer(0);
+        view.set_layer(locked);
+        view.select_layer(1);
+        view.rotate90();
+        assert!(view.take_refused() && view.layer_cells(1)[0].alpha == 128);
+    }
+
+    #[test]
+    fn stamps_paint_each_cell_once_per_stroke() {
+        let (mut view, brush) = canvas(Vec2::new(10, 3));
+        let half = Cell {backcolor: Color::Rgb(0, 0, 0), alpha: 128, ..Cell::CLEAR};
+        view.clipboard = Some(Board {size: Vec2::new(2, 1), cells: vec![half, half]});
+        assert!(view.set_stamp());
+        brush.borrow_mut().shape = Shape::Stamp;
+
+        view.touched = vec![false; 30];
+        for x in 2..6 {
+            view.dab(Vec2::new(x, 1));
+        }
+        let once = stack(Cell::default(), half, 100, Blend::Normal);
+        assert!((2..7).all(|x| view.overlay[x + 10] == once));
+        assert!(view.overlay[1 + 10] == Cell::default() && view.overlay[7 + 10] == Cell::default());
+    }
+}
@@ -0,0 +1,141 @@
+// Bringing pictures down to a few colours, for terminals and formats that don't have all 16 million.
+// Without dithering every pixel simply becomes the closest colour. Error diffusion passes what that got wrong on to
+// the pixels that come after it, ordered dithering nudges pixels up or down by a fixed pattern before picking.
+
+use image::{Rgb, RgbImage};
+
+use crate::palette::{nearest, xterm_256, XTERM_16};
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Dither {
+    None,
+    FloydSteinberg,
+    Atkinson,
+    Bayer,
+}
+
+pub fn xterm_16() -> Vec<(u8, u8, u8)> {
+    XTERM_16.to_vec()
+}
+
+pub fn xterm_256_all() -> Vec<(u8, u8, u8)> {
+    (0..=255).map(xterm_256).collect()
+}
+
+pub fn parse_swatches(text: &str) -> Option<Vec<(u8, u8, u8)>> {
+    // A list of colours like ff0000,00ff00,#0000ff.
+    let colors: Option<Vec<(u8, u8, u8)>> = text.split(',')
+        .map(|c| {
+            let c = c.trim_start_matches('#');
+            if c.len() != 6 || !c.bytes().all(|b| b.is_ascii_hexdigit()) {
+                return None;
+            }
+            let v = u32::from_str_radix(c, 16).ok()?;
+            Some(((v >> 16) as u8, (v >> 8) as u8, v as u8))
+        })
+        .collect();
+
+    colors.filter(|c| !c.is_empty() && c.len() <= 256)
+}
+
+// Where each pixel of a 4x4 tile falls between 0 and 16, so that neighbouring pixels are rounded in different directions.
+const BAYER: [[u8; 4]; 4] = [
+    [0, 8, 2, 10],
+    [12, 4, 14, 6],
+    [3, 11, 1, 9],
+    [15, 7, 13, 5],
+];
+
+pub fn quantize(img: &mut RgbImage, colors: &[(u8, u8, u8)], dither: Dither) {
+    quantize_where(img, colors, dither, None);
+}
+
+pub fn quantize_where(img: &mut RgbImage, colors: &[(u8, u8, u8)], dither: Dither, mask: Option<&[bool]>) {
+    // Only the pixels the mask lets through are changed, the others neither pass on an error nor take one.
+    if colors.is_empty() {
+        return;
+    }
+    let (w, h) = (img.width() as usize, img.height() as usize);
+
+    // How far apart the colours are on average, which is how hard ordered dithering has to push.
+    let spread = 255. / (colors.len() as f32).cbrt().max(1.);
+
+    // The shares of the error that go to the pixels around, as (dx, dy, share).
+    let diffusion: &[(isize, usize, f32)] = match dither {
+        Dither::FloydSteinberg => &[(1, 0, 7. / 16.), (-1, 1, 3. / 16.), (0, 1, 5. / 16.), (1, 1, 1. / 16.)],
+        Dither::Atkinson => &[(1, 0, 0.125), (2, 0, 0.125), (-1, 1, 0.125), (0, 1, 0.125), (1, 1, 0.125), (0, 2, 0.125)],
+        _ => &[],
+    };
+
+    let mut values: Vec<[f32; 3]> = img.pixels().map(|p| [p[0] as f32, p[1] as f32, p[2] as f32]).collect();
+
+    let inside = |i: usize| match mask {
+        Some(m) => m.get(i).copied().unwrap_or(false),
+        None => true,
+    };
+
+    for i in (0..w * h).filter(|&i| inside(i)) {
+        let (x, y) = (i % w, i / w);
+        let mut wanted = values[i];
+        if dither == Dither::Bayer {
+            let offset = (BAYER[y % 4][x % 4] as f32 + 0.5) / 16. - 0.5;
+            for c in wanted.iter_mut() {
+                *c += offset * spread;
+            }
+        }
+
+        let clamp = |v: f32| v.round().clamp(0., 255.) as u8;
+        let picked = colors[nearest(colors, (clamp(wanted[0]), clamp(wanted[1]), clamp(wanted[2])))];
+        img.put_pixel(x as u32, y as u32, Rgb([picked.0, picked.1, picked.2]));
+
+        let error = [values[i][0] - picked.0 as f32, values[i][1] - picked.1 as f32, values[i][2] - picked.2 as f32];
+        for (dx, dy, share) in diffusion {
+            let nx = x as isize + dx;
+            if nx >= 0 && (nx as usize) < w && y + dy < h && inside((y + dy) * w + nx as usize) {
+                let n = &mut values[(y + dy) * w + nx as usize];
+                for c in 0..3 {
+                    n[c] += error[c] * share;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn swatches_are_six_hex_digits() {
+        assert_eq!(parse_swatches("ff0000,#00ff00,0000ff"), Some(vec![(255, 0, 0), (0, 255, 0), (0, 0, 255)]));
+        assert_eq!(parse_swatches("+12345"), None);
+        assert_eq!(parse_swatches("-12345"), None);
+        assert_eq!(parse_swatches("fff"), None);
+        assert_eq!(parse_swatches(""), None);
+    }
+
+    #[test]
+    fn quantize_picks_and_spreads() {
+        let colors = [(0, 0, 0), (255, 255, 255)];
+        let grey = RgbImage::from_pixel(8, 8, Rgb([128, 128, 128]));
+        let white = |img: &RgbImage| img.pixels().filter(|p| p[0] == 255).count();
+
+        let mut img = grey.clone();
+        quantize(&mut img, &colors, Dither::None);
+        assert_eq!(white(&img), 64);
+
+        // Dithered, middle grey is about half and half.
+        for dither in [Dither::FloydSteinberg, Dither::Atkinson, Dither::Bayer] {
+            let mut img = grey.clone();
+            quantize(&mut img, &colors, dither);
+            assert!(img.pixels().all(|p| p[0] == 0 || p[0] == 255));
+            assert!((24..=40).contains(&white(&img)), "{:?}: {}", dither, white(&img));
+        }
+
+        // Outside the mask nothing changes.
+        let mut img = grey.clone();
+        let mask: Vec<bool> = (0..64).map(|i| i % 8 < 4).collect();
+        quantize_where(&mut img, &colors, Dither::FloydSteinberg, Some(&mask));
+        assert!(img.enumerate_pixels().all(|(x, _, p)| x < 4 || p[0] == 128));
+    }
+}
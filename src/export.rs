@@ -0,0 +1,267 @@
+// Writing the canvas out in formats other programs understand. Everything here works on a plain list of cells, row by row.
+
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use cursive::Vec2;
+use image::{Rgb, RgbImage};
+
+use crate::canvas::Cell;
+use crate::font;
+use crate::import::is_wide;
+use crate::palette::{nearest, nearest_256, to_rgb, XTERM_16};
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum ColorDepth {
+    TrueColor,
+    Xterm256,
+    Xterm16,
+}
+
+#[derive(Clone, Copy)]
+pub struct AnsiOptions {
+    pub depth: ColorDepth,
+    pub minimal: bool, // Only write a colour when it differs from the cell before.
+}
+
+impl Default for AnsiOptions {
+    fn default() -> Self {
+        AnsiOptions {depth: ColorDepth::TrueColor, minimal: false}
+    }
+}
+
+fn sgr(rgb: (u8, u8, u8), depth: ColorDepth, background: bool) -> String {
+    // The parameters of a "select graphic rendition" escape that sets one colour.
+    let (r, g, b) = rgb;
+    let base = if background {40} else {30};
+
+    match depth {
+        ColorDepth::TrueColor => format!("{};2;{};{};{}", base + 8, r, g, b),
+        ColorDepth::Xterm256 => format!("{};5;{}", base + 8, nearest_256(rgb)),
+        ColorDepth::Xterm16 => {
+            let i = nearest(&XTERM_16, rgb);
+            if i < 8 {format!("{}", base + i)} else {format!("{}", base + 60 + i - 8)}
+        }
+    }
+}
+
+pub fn to_ansi(size: Vec2, cells: &[Cell], options: AnsiOptions) -> String {
+    // Text with colour escapes, so that cat shows the canvas just like kakikun does.
+    // Every line ends by resetting the colours, otherwise terminals paint the background all the way to the edge.
+    let mut text = String::with_capacity(cells.len() * 40);
+
+    for row in cells.chunks(size.x.max(1)) {
+        let mut last: Option<(String, String)> = None;
+        let mut covered = false;
+
+        for cell in row {
+            // The terminal already moved past the blank after a wide character, printing it would push the row along.
+            if covered && cell.symbol == ' ' {
+                covered = false;
+                continue;
+            }
+            covered = is_wide(cell.symbol);
+
+            let color = sgr(to_rgb(cell.color), options.depth, false);
+            let backcolor = sgr(to_rgb(cell.backcolor), options.depth, true);
+
+            let codes: Vec<&str> = match &last {
+                Some((c, b)) if options.minimal => {
+                    let mut codes = Vec::new();
+                    if *c != color {codes.push(color.as_str());}
+                    if *b != backcolor {codes.push(backcolor.as_str());}
+                    codes
+                }
+                _ => vec![color.as_str(), backcolor.as_str()],
+            };
+            if !codes.is_empty() {
+                let _ = write!(text, "\x1b[{}m", codes.join(";"));
+            }
+
+            text.push(if cell.symbol.is_control() {' '} else {cell.symbol});
+            last = Some((color, backcolor));
+        }
+
+        text.push_str("\x1b[0m\n");
+    }
+
+    text
+}
+
+#[derive(Clone)]
+pub struct HtmlOptions {
+    pub font: String, // Font families as CSS wants them, separated by commas. Monospace is always added as a fallback.
+    pub line_height: f32,
+    pub background: (u8, u8, u8), // Of the page around the canvas.
+}
+
+impl Default for HtmlOptions {
+    fn default() -> Self {
+        HtmlOptions {font: "Menlo,Consolas,DejaVu Sans Mono".to_string(), line_height: 1.0, background: (255, 255, 255)}
+    }
+}
+
+fn escape_html(symbol: char, text: &mut String) {
+    match symbol {
+        '<' => text.push_str("&lt;"),
+        '>' => text.push_str("&gt;"),
+        '&' => text.push_str("&amp;"),
+        '"' => text.push_str("&quot;"),
+        '\'' => text.push_str("&#39;"),
+        c if c.is_control() => text.push(' '),
+        c => text.push(c),
+    }
+}
+
+pub fn to_html(size: Vec2, cells: &[Cell], options: &HtmlOptions) -> String {
+    // A page that needs nothing else: the canvas is one <pre>, with a <span> for every run of cells that look the same.
+    let mut text = String::with_capacity(512 + cells.len() * 8);
+    let (r, g, b) = options.background;
+
+    text.push_str("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>kakikun</title>\n<style>\n");
+    let _ = writeln!(text, "body {{background: #{:02x}{:02x}{:02x}; margin: 2em;}}", r, g, b);
+    let _ = writeln!(text, "pre {{font-family: {}, monospace; line-height: {}; margin: 0;}}", options.font, options.line_height);
+    text.push_str("</style>\n</head>\n<body>\n<pre>");
+
+    for row in cells.chunks(size.x.max(1)) {
+        let mut last = None;
+        let mut covered = false;
+
+        for cell in row {
+            // Like in a .txt, the blank after a wide character is already taken up by it.
+            if covered && cell.symbol == ' ' {
+                covered = false;
+                continue;
+            }
+            covered = is_wide(cell.symbol);
+
+            let style = (to_rgb(cell.color), to_rgb(cell.backcolor));
+            if last != Some(style) {
+                if last.is_some() {
+                    text.push_str("</span>");
+                }
+                let ((r, g, b), (r2, g2, b2)) = style;
+                let _ = write!(text, "<span style=\"color:#{:02x}{:02x}{:02x};background:#{:02x}{:02x}{:02x}\">", r, g, b, r2, g2, b2);
+                last = Some(style);
+            }
+
+            escape_html(cell.symbol, &mut text);
+        }
+
+        if last.is_some() {
+            text.push_str("</span>");
+        }
+        text.push('\n');
+    }
+
+    text.push_str("</pre>\n</body>\n</html>\n");
+    text
+}
+
+#[derive(Clone)]
+pub struct SvgOptions {
+    pub aspect: f32, // How many times taller than wide a cell is, about 2 in most terminals.
+    pub font: String,
+}
+
+impl Default for SvgOptions {
+    fn default() -> Self {
+        SvgOptions {aspect: 2.0, font: "Menlo,Consolas,DejaVu Sans Mono".to_string()}
+    }
+}
+
+pub fn to_svg(size: Vec2, cells: &[Cell], options: &SvgOptions) -> String {
+    // A vector image: backgrounds as rectangles, merged along each row where the colour stays the same, and symbols as text on top.
+    // Cells are 10 units wide, so the picture scales to any size without losing anything.
+    let mut text = String::with_capacity(256 + cells.len() * 64);
+    let w = 10.0f32;
+    let h = w * options.aspect;
+    let font_size = (w / 0.6).min(h); // Most monospace glyphs are about 0.6 times as wide as they are high.
+    let hex = |(r, g, b): (u8, u8, u8)| format!("#{:02x}{:02x}{:02x}", r, g, b);
+
+    let _ = writeln!(text, "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" viewBox=\"0 0 {} {}\">",
+        size.x as f32 * w, size.y as f32 * h, size.x as f32 * w, size.y as f32 * h);
+
+    text.push_str("<g shape-rendering=\"crispEdges\">\n");
+    for (y, row) in cells.chunks(size.x.max(1)).enumerate() {
+        let mut x = 0;
+        while x < row.len() {
+            let color = to_rgb(row[x].backcolor);
+            let run = row[x..].iter().take_while(|c| to_rgb(c.backcolor) == color).count();
+            let _ = writeln!(text, "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"{}\"/>",
+                x as f32 * w, y as f32 * h, run as f32 * w, h, hex(color));
+            x += run;
+        }
+    }
+    text.push_str("</g>\n");
+
+    let _ = writeln!(text, "<g font-family=\"{}, monospace\" font-size=\"{}\" text-anchor=\"middle\" dominant-baseline=\"central\" xml:space=\"preserve\">",
+        options.font, font_size);
+    for (i, cell) in cells.iter().enumerate() {
+        if cell.symbol.is_whitespace() || cell.symbol.is_control() {
+            continue;
+        }
+
+        // A wide character is centred over its own cell and the one after it.
+        let (x, y) = (i % size.x.max(1), i / size.x.max(1));
+        let span = if is_wide(cell.symbol) {2.0} else {1.0};
+        let _ = write!(text, "<text x=\"{}\" y=\"{}\" fill=\"{}\">", x as f32 * w + span * w / 2.0, y as f32 * h + h / 2.0, hex(to_rgb(cell.color)));
+        escape_html(cell.symbol, &mut text);
+        text.push_str("</text>\n");
+    }
+    text.push_str("</g>\n</svg>\n");
+
+    text
+}
+
+pub fn to_render(size: Vec2, cells: &[Cell], cell: Vec2) -> (RgbImage, usize) {
+    // An image that looks like a screenshot: every cell is cell.x by cell.y pixels, its symbol drawn on top in the character colour.
+    // Also returns how many symbols weren't in the font, those show up as hollow boxes.
+    let mut image = RgbImage::new((size.x * cell.x) as u32, (size.y * cell.y) as u32);
+    let columns = size.x.max(1);
+
+    for (i, c) in cells.iter().enumerate() {
+        let (x, y) = (i % columns, i / columns);
+        let (r, g, b) = to_rgb(c.backcolor);
+        for py in 0..cell.y {
+            for px in 0..cell.x {
+                image.put_pixel((x * cell.x + px) as u32, (y * cell.y + py) as u32, Rgb([r, g, b]));
+            }
+        }
+    }
+
+    // Symbols go on top once all backgrounds are down, as a wide character reaches into the blank cell after it.
+    let mut glyphs: HashMap<(char, usize), Option<Vec<bool>>> = HashMap::new();
+    let mut boxes: HashMap<usize, Vec<bool>> = HashMap::new();
+    let mut missing = 0;
+    let mut covered = false;
+
+    for (i, c) in cells.iter().enumerate() {
+        let (x, y) = (i % columns, i / columns);
+        if x == 0 {
+            covered = false;
+        }
+        if covered && c.symbol == ' ' {
+            covered = false;
+            continue;
+        }
+        let span = if is_wide(c.symbol) && x + 1 < size.x {2} else {1};
+        covered = span == 2;
+
+        let (w, h) = (cell.x * span, cell.y);
+        let mask = match glyphs.entry((c.symbol, span)).or_insert_with(|| font::rasterize(c.symbol, w, h)) {
+            Some(m) => m,
+            None => {
+                missing += 1;
+                boxes.entry(span).or_insert_with(|| font::missing(w, h))
+            }
+        };
+
+        let (r, g, b) = to_rgb(c.color);
+        for (j, _) in mask.iter().enumerate().filter(|(_, on)| **on) {
+            image.put_pixel((x * cell.x + j % w) as u32, (y * cell.y + j / w) as u32, Rgb([r, g, b]));
+        }
+    }
+
+    (image, missing)
+}
@@ -0,0 +1,278 @@
+// A small bitmap font, so that image exports can show symbols and not just background colours.
+//
+// Printable ASCII comes from a table of 8x8 glyphs, one byte per row with the highest bit on the left.
+// Block elements, shades, box drawing and braille are drawn from their shapes instead, so they line up
+// at any cell size the way they do in a terminal. Anything else is missing and callers draw a hollow box instead.
+
+// Sorted by character, so it can be searched.
+const ASCII: [(char, [u8; 8]); 94] = [
+    ('!', [0x10, 0x10, 0x10, 0x10, 0x10, 0x00, 0x10, 0x00]),
+    ('"', [0x28, 0x28, 0x28, 0x00, 0x00, 0x00, 0x00, 0x00]),
+    ('#', [0x28, 0x28, 0x7c, 0x28, 0x7c, 0x28, 0x28, 0x00]),
+    ('$', [0x10, 0x3c, 0x50, 0x38, 0x14, 0x78, 0x10, 0x00]),
+    ('%', [0x60, 0x64, 0x08, 0x10, 0x20, 0x4c, 0x0c, 0x00]),
+    ('&', [0x30, 0x48, 0x50, 0x20, 0x54, 0x48, 0x34, 0x00]),
+    ('\'', [0x10, 0x10, 0x20, 0x00, 0x00, 0x00, 0x00, 0x00]),
+    ('(', [0x08, 0x10, 0x20, 0x20, 0x20, 0x10, 0x08, 0x00]),
+    (')', [0x20, 0x10, 0x08, 0x08, 0x08, 0x10, 0x20, 0x00]),
+    ('*', [0x00, 0x10, 0x54, 0x38, 0x54, 0x10, 0x00, 0x00]),
+    ('+', [0x00, 0x10, 0x10, 0x7c, 0x10, 0x10, 0x00, 0x00]),
+    (',', [0x00, 0x00, 0x00, 0x00, 0x00, 0x10, 0x10, 0x20]),
+    ('-', [0x00, 0x00, 0x00, 0x7c, 0x00, 0x00, 0x00, 0x00]),
+    ('.', [0x00, 0x00, 0x00, 0x00, 0x00, 0x30, 0x30, 0x00]),
+    ('/', [0x00, 0x04, 0x08, 0x10, 0x20, 0x40, 0x00, 0x00]),
+    ('0', [0x38, 0x44, 0x4c, 0x54, 0x64, 0x44, 0x38, 0x00]),
+    ('1', [0x10, 0x30, 0x10, 0x10, 0x10, 0x10, 0x38, 0x00]),
+    ('2', [0x38, 0x44, 0x04, 0x08, 0x10, 0x20, 0x7c, 0x00]),
+    ('3', [0x7c, 0x08, 0x10, 0x08, 0x04, 0x44, 0x38, 0x00]),
+    ('4', [0x08, 0x18, 0x28, 0x48, 0x7c, 0x08, 0x08, 0x00]),
+    ('5', [0x7c, 0x40, 0x78, 0x04, 0x04, 0x44, 0x38, 0x00]),
+    ('6', [0x18, 0x20, 0x40, 0x78, 0x44, 0x44, 0x38, 0x00]),
+    ('7', [0x7c, 0x04, 0x08, 0x10, 0x20, 0x20, 0x20, 0x00]),
+    ('8', [0x38, 0x44, 0x44, 0x38, 0x44, 0x44, 0x38, 0x00]),
+    ('9', [0x38, 0x44, 0x44, 0x3c, 0x04, 0x08, 0x30, 0x00]),
+    (':', [0x00, 0x30, 0x30, 0x00, 0x30, 0x30, 0x00, 0x00]),
+    (';', [0x00, 0x30, 0x30, 0x00, 0x30, 0x30, 0x10, 0x20]),
+    ('<', [0x08, 0x10, 0x20, 0x40, 0x20, 0x10, 0x08, 0x00]),
+    ('=', [0x00, 0x00, 0x7c, 0x00, 0x7c, 0x00, 0x00, 0x00]),
+    ('>', [0x20, 0x10, 0x08, 0x04, 0x08, 0x10, 0x20, 0x00]),
+    ('?', [0x38, 0x44, 0x04, 0x08, 0x10, 0x00, 0x10, 0x00]),
+    ('@', [0x38, 0x44, 0x04, 0x34, 0x54, 0x54, 0x38, 0x00]),
+    ('A', [0x38, 0x44, 0x44, 0x7c, 0x44, 0x44, 0x44, 0x00]),
+    ('B', [0x78, 0x44, 0x44, 0x78, 0x44, 0x44, 0x78, 0x00]),
+    ('C', [0x38, 0x44, 0x40, 0x40, 0x40, 0x44, 0x38, 0x00]),
+    ('D', [0x70, 0x48, 0x44, 0x44, 0x44, 0x48, 0x70, 0x00]),
+    ('E', [0x7c, 0x40, 0x40, 0x78, 0x40, 0x40, 0x7c, 0x00]),
+    ('F', [0x7c, 0x40, 0x40, 0x78, 0x40, 0x40, 0x40, 0x00]),
+    ('G', [0x38, 0x44, 0x40, 0x5c, 0x44, 0x44, 0x3c, 0x00]),
+    ('H', [0x44, 0x44, 0x44, 0x7c, 0x44, 0x44, 0x44, 0x00]),
+    ('I', [0x38, 0x10, 0x10, 0x10, 0x10, 0x10, 0x38, 0x00]),
+    ('J', [0x1c, 0x08, 0x08, 0x08, 0x08, 0x48, 0x30, 0x00]),
+    ('K', [0x44, 0x48, 0x50, 0x60, 0x50, 0x48, 0x44, 0x00]),
+    ('L', [0x40, 0x40, 0x40, 0x40, 0x40, 0x40, 0x7c, 0x00]),
+    ('M', [0x44, 0x6c, 0x54, 0x54, 0x44, 0x44, 0x44, 0x00]),
+    ('N', [0x44, 0x44, 0x64, 0x54, 0x4c, 0x44, 0x44, 0x00]),
+    ('O', [0x38, 0x44, 0x44, 0x44, 0x44, 0x44, 0x38, 0x00]),
+    ('P', [0x78, 0x44, 0x44, 0x78, 0x40, 0x40, 0x40, 0x00]),
+    ('Q', [0x38, 0x44, 0x44, 0x44, 0x54, 0x48, 0x34, 0x00]),
+    ('R', [0x78, 0x44, 0x44, 0x78, 0x50, 0x48, 0x44, 0x00]),
+    ('S', [0x3c, 0x40, 0x40, 0x38, 0x04, 0x04, 0x78, 0x00]),
+    ('T', [0x7c, 0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x00]),
+    ('U', [0x44, 0x44, 0x44, 0x44, 0x44, 0x44, 0x38, 0x00]),
+    ('V', [0x44, 0x44, 0x44, 0x44, 0x44, 0x28, 0x10, 0x00]),
+    ('W', [0x44, 0x44, 0x44, 0x54, 0x54, 0x54, 0x28, 0x00]),
+    ('X', [0x44, 0x44, 0x28, 0x10, 0x28, 0x44, 0x44, 0x00]),
+    ('Y', [0x44, 0x44, 0x28, 0x10, 0x10, 0x10, 0x10, 0x00]),
+    ('Z', [0x7c, 0x04, 0x08, 0x10, 0x20, 0x40, 0x7c, 0x00]),
+    ('[', [0x38, 0x20, 0x20, 0x20, 0x20, 0x20, 0x38, 0x00]),
+    ('\\', [0x00, 0x40, 0x20, 0x10, 0x08, 0x04, 0x00, 0x00]),
+    (']', [0x38, 0x08, 0x08, 0x08, 0x08, 0x08, 0x38, 0x00]),
+    ('^', [0x10, 0x28, 0x44, 0x00, 0x00, 0x00, 0x00, 0x00]),
+    ('_', [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x7c]),
+    ('`', [0x20, 0x10, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00]),
+    ('a', [0x00, 0x00, 0x38, 0x04, 0x3c, 0x44, 0x3c, 0x00]),
+    ('b', [0x40, 0x40, 0x58, 0x64, 0x44, 0x44, 0x78, 0x00]),
+    ('c', [0x00, 0x00, 0x38, 0x40, 0x40, 0x44, 0x38, 0x00]),
+    ('d', [0x04, 0x04, 0x34, 0x4c, 0x44, 0x44, 0x3c, 0x00]),
+    ('e', [0x00, 0x00, 0x38, 0x44, 0x7c, 0x40, 0x38, 0x00]),
+    ('f', [0x18, 0x24, 0x20, 0x70, 0x20, 0x20, 0x20, 0x00]),
+    ('g', [0x00, 0x00, 0x3c, 0x44, 0x44, 0x3c, 0x04, 0x38]),
+    ('h', [0x40, 0x40, 0x58, 0x64, 0x44, 0x44, 0x44, 0x00]),
+    ('i', [0x10, 0x00, 0x30, 0x10, 0x10, 0x10, 0x38, 0x00]),
+    ('j', [0x08, 0x00, 0x18, 0x08, 0x08, 0x08, 0x48, 0x30]),
+    ('k', [0x40, 0x40, 0x48, 0x50, 0x60, 0x50, 0x48, 0x00]),
+    ('l', [0x30, 0x10, 0x10, 0x10, 0x10, 0x10, 0x38, 0x00]),
+    ('m', [0x00, 0x00, 0x68, 0x54, 0x54, 0x44, 0x44, 0x00]),
+    ('n', [0x00, 0x00, 0x58, 0x64, 0x44, 0x44, 0x44, 0x00]),
+    ('o', [0x00, 0x00, 0x38, 0x44, 0x44, 0x44, 0x38, 0x00]),
+    ('p', [0x00, 0x00, 0x78, 0x44, 0x44, 0x78, 0x40, 0x40]),
+    ('q', [0x00, 0x00, 0x3c, 0x44, 0x44, 0x3c, 0x04, 0x04]),
+    ('r', [0x00, 0x00, 0x58, 0x64, 0x40, 0x40, 0x40, 0x00]),
+    ('s', [0x00, 0x00, 0x3c, 0x40, 0x38, 0x04, 0x78, 0x00]),
+    ('t', [0x20, 0x20, 0x70, 0x20, 0x20, 0x24, 0x18, 0x00]),
+    ('u', [0x00, 0x00, 0x44, 0x44, 0x44, 0x4c, 0x34, 0x00]),
+    ('v', [0x00, 0x00, 0x44, 0x44, 0x44, 0x28, 0x10, 0x00]),
+    ('w', [0x00, 0x00, 0x44, 0x44, 0x54, 0x54, 0x28, 0x00]),
+    ('x', [0x00, 0x00, 0x44, 0x28, 0x10, 0x28, 0x44, 0x00]),
+    ('y', [0x00, 0x00, 0x44, 0x44, 0x44, 0x3c, 0x04, 0x38]),
+    ('z', [0x00, 0x00, 0x7c, 0x08, 0x10, 0x20, 0x7c, 0x00]),
+    ('{', [0x08, 0x10, 0x10, 0x20, 0x10, 0x10, 0x08, 0x00]),
+    ('|', [0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x00]),
+    ('}', [0x20, 0x10, 0x10, 0x08, 0x10, 0x10, 0x20, 0x00]),
+    ('~', [0x00, 0x00, 0x20, 0x54, 0x08, 0x00, 0x00, 0x00]),
+];
+
+// The lines of box drawing characters going up, right, down and left: 0 none, 1 light, 2 heavy, 3 double.
+const BOXES: [(char, [u8; 4]); 49] = [
+    ('─', [0, 1, 0, 1]), ('━', [0, 2, 0, 2]), ('│', [1, 0, 1, 0]), ('┃', [2, 0, 2, 0]),
+    ('┌', [0, 1, 1, 0]), ('┏', [0, 2, 2, 0]), ('┐', [0, 0, 1, 1]), ('┓', [0, 0, 2, 2]),
+    ('└', [1, 1, 0, 0]), ('┗', [2, 2, 0, 0]), ('┘', [1, 0, 0, 1]), ('┛', [2, 0, 0, 2]),
+    ('├', [1, 1, 1, 0]), ('┣', [2, 2, 2, 0]), ('┤', [1, 0, 1, 1]), ('┫', [2, 0, 2, 2]),
+    ('┬', [0, 1, 1, 1]), ('┳', [0, 2, 2, 2]), ('┴', [1, 1, 0, 1]), ('┻', [2, 2, 0, 2]),
+    ('┼', [1, 1, 1, 1]), ('╋', [2, 2, 2, 2]),
+    ('═', [0, 3, 0, 3]), ('║', [3, 0, 3, 0]), ('╔', [0, 3, 3, 0]), ('╗', [0, 0, 3, 3]),
+    ('╚', [3, 3, 0, 0]), ('╝', [3, 0, 0, 3]), ('╠', [3, 3, 3, 0]), ('╣', [3, 0, 3, 3]),
+    ('╦', [0, 3, 3, 3]), ('╩', [3, 3, 0, 3]), ('╬', [3, 3, 3, 3]),
+    ('╭', [0, 1, 1, 0]), ('╮', [0, 0, 1, 1]), ('╯', [1, 0, 0, 1]), ('╰', [1, 1, 0, 0]),
+    ('╴', [0, 0, 0, 1]), ('╵', [1, 0, 0, 0]), ('╶', [0, 1, 0, 0]), ('╷', [0, 0, 1, 0]),
+    ('╸', [0, 0, 0, 2]), ('╹', [2, 0, 0, 0]), ('╺', [0, 2, 0, 0]), ('╻', [0, 0, 2, 0]),
+    ('╼', [0, 2, 0, 1]), ('╽', [1, 0, 2, 0]), ('╾', [0, 1, 0, 2]), ('╿', [2, 0, 1, 0]),
+];
+
+pub fn rasterize(symbol: char, w: usize, h: usize) -> Option<Vec<bool>> {
+    // Which pixels of a w by h cell the symbol covers, row by row. None if the font doesn't have it.
+    let covers: Box<dyn Fn(usize, usize) -> bool> = match symbol as u32 {
+        _ if symbol.is_whitespace() => Box::new(|_, _| false),
+        0x21..=0x7e => {
+            let rows = ASCII[ASCII.binary_search_by_key(&symbol, |(c, _)| *c).ok()?].1;
+            Box::new(move |x, y| rows[y * 8 / h] & (0x80 >> (x * 8 / w)) != 0)
+        }
+        0x2580..=0x259f => Box::new(move |x, y| block(symbol, x, y, w, h)),
+        0x2571..=0x2573 => Box::new(move |x, y| diagonal(symbol, x, y, w, h)),
+        0x2500..=0x257f => {
+            let lines = BOXES[BOXES.iter().position(|(c, _)| *c == symbol)?].1;
+            Box::new(move |x, y| box_line(lines, x, y, w, h))
+        }
+        0x2800..=0x28ff => Box::new(move |x, y| braille(symbol as u32 - 0x2800, x, y, w, h)),
+        _ => return None,
+    };
+
+    Some((0..w * h).map(|i| covers(i % w, i / w)).collect())
+}
+
+pub fn missing(w: usize, h: usize) -> Vec<bool> {
+    // The hollow box drawn for symbols the font doesn't have, one pixel in from the edge of the cell.
+    (0..w * h).map(|i| {
+        let (x, y) = (i % w, i / w);
+        let inside = x >= 1 && y >= 1 && x + 1 < w && y + 1 < h;
+        inside && (x == 1 || y == 1 || x + 2 == w || y + 2 == h)
+    }).collect()
+}
+
+fn block(symbol: char, x: usize, y: usize, w: usize, h: usize) -> bool {
+    // The block elements, in eighths, halves, quarters and shades.
+    let (fx, fy) = ((x as f32 + 0.5) / w as f32, (y as f32 + 0.5) / h as f32);
+    let (left, top) = (fx < 0.5, fy < 0.5);
+    let n = symbol as u32;
+
+    match symbol {
+        '▀' => top,
+        '▁'..='█' => fy >= 1. - (n - 0x2580) as f32 / 8.,
+        '▉'..='▏' => fx < (0x2590 - n) as f32 / 8.,
+        '▐' => !left,
+        '░' => (x | y) & 1 == 0,
+        '▒' => (x ^ y) & 1 == 0,
+        '▓' => x & y & 1 == 0,
+        '▔' => fy < 1. / 8.,
+        '▕' => fx >= 7. / 8.,
+        // The quadrants, upper left, upper right, lower left and lower right.
+        _ => {
+            let quadrants = match symbol {
+                '▖' => [false, false, true, false],
+                '▗' => [false, false, false, true],
+                '▘' => [true, false, false, false],
+                '▙' => [true, false, true, true],
+                '▚' => [true, false, false, true],
+                '▛' => [true, true, true, false],
+                '▜' => [true, true, false, true],
+                '▝' => [false, true, false, false],
+                '▞' => [false, true, true, false],
+                _ => [false, true, true, true],
+            };
+            quadrants[(!top as usize) * 2 + !left as usize]
+        }
+    }
+}
+
+fn diagonal(symbol: char, x: usize, y: usize, w: usize, h: usize) -> bool {
+    let (fx, fy) = ((x as f32 + 0.5) / w as f32, (y as f32 + 0.5) / h as f32);
+    let rising = (fx - (1. - fy)).abs() * (w as f32) < 0.75;
+    let falling = (fx - fy).abs() * (w as f32) < 0.75;
+
+    match symbol {
+        '╱' => rising,
+        '╲' => falling,
+        _ => rising || falling,
+    }
+}
+
+fn extent(weight: u8, centre: usize, t: usize) -> (usize, usize) {
+    // Where a line of some weight lies across its direction, from and to. Light is t thick, heavy twice that,
+    // double is two light lines with a light line's gap between them.
+    let lo = centre.saturating_sub(t / 2);
+    match weight {
+        2 => (centre.saturating_sub(t), centre + t),
+        3 => (lo.saturating_sub(t), lo + 2 * t),
+        _ => (lo, lo + t),
+    }
+}
+
+fn on_line(weight: u8, p: usize, centre: usize, t: usize) -> bool {
+    let (lo, hi) = extent(weight, centre, t);
+    let (gap_lo, gap_hi) = extent(1, centre, t);
+    p >= lo && p < hi && !(weight == 3 && p >= gap_lo && p < gap_hi)
+}
+
+fn box_line(lines: [u8; 4], x: usize, y: usize, w: usize, h: usize) -> bool {
+    // Each line goes from its edge of the cell to the far side of the lines crossing it, so corners close up.
+    let [up, right, down, left] = lines;
+    let (tx, ty) = ((w / 8).max(1), (h / 16).max(1));
+    let (cx, cy) = (w / 2, h / 2);
+
+    let across = |a: u8, b: u8, centre: usize, t: usize| {
+        let (lo1, hi1) = extent(a.max(1), centre, t);
+        let (lo2, hi2) = extent(b.max(1), centre, t);
+        (lo1.min(lo2), hi1.max(hi2))
+    };
+    let (top, bottom) = across(left, right, cy, ty);
+    let (start, end) = across(up, down, cx, tx);
+
+    (up > 0 && y < bottom && on_line(up, x, cx, tx))
+        || (down > 0 && y >= top && on_line(down, x, cx, tx))
+        || (left > 0 && x < end && on_line(left, y, cy, ty))
+        || (right > 0 && x >= start && on_line(right, y, cy, ty))
+}
+
+pub fn braille_bit(col: usize, row: usize) -> u32 {
+    // The bit of a braille character for the dot in column col and row row, both counting from 0 at the top left.
+    match (col, row) {
+        (0, 3) => 1 << 6,
+        (1, 3) => 1 << 7,
+        (c, r) => 1 << (c * 3 + r),
+    }
+}
+
+fn braille(dots: u32, x: usize, y: usize, w: usize, h: usize) -> bool {
+    // Two columns of four dots, each in the middle of its quarter of the cell.
+    let (fx, fy) = ((x as f32 + 0.5) / w as f32 * 2., (y as f32 + 0.5) / h as f32 * 4.);
+    let (sx, sy) = (fx.fract(), fy.fract());
+
+    dots & braille_bit(fx as usize, fy as usize) != 0 && (0.25..0.75).contains(&sx) && (0.25..0.75).contains(&sy)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_printable_ascii_character_in_order() {
+        // rasterize looks them up with a binary search.
+        assert!(ASCII.iter().map(|(c, _)| *c).eq('!'..='~'));
+    }
+
+    #[test]
+    fn shapes_cover_the_right_pixels() {
+        let count = |symbol: char| rasterize(symbol, 8, 16).map(|p| p.iter().filter(|b| **b).count());
+        assert_eq!(count(' '), Some(0));
+        assert_eq!(count('█'), Some(128));
+        assert!(rasterize('▀', 8, 16).unwrap()[..64].iter().all(|b| *b));
+        assert!(!rasterize('▀', 8, 16).unwrap()[64..].iter().any(|b| *b));
+        assert!(count('─').unwrap() > 0 && count('A').unwrap() > 0);
+        assert_eq!(count('描'), None);
+
+        // Dots 1 and 8 of braille are the top left and the bottom right.
+        let dots = rasterize('\u{2881}', 8, 16).unwrap();
+        assert!(dots[8 + 1] && dots[14 * 8 + 5]);
+        assert_eq!(dots.iter().filter(|b| **b).count(), count('\u{2801}').unwrap() * 2);
+    }
+}
@@ -0,0 +1,276 @@
+// Undo and redo for the canvas.
+// Copying the whole canvas for every step got expensive on large canvases, so we only keep what changed between two steps.
+// The last recorded state is kept in full, every step knows how to get from the one before it to the next and back again.
+// Layers are recorded as one tall board, one layer under the other, with their names and settings on the side.
+
+use std::collections::VecDeque;
+use std::mem::size_of;
+
+use cursive::Vec2;
+
+use crate::canvas::{Board, Cell, Layer};
+
+struct Snapshot {
+    // A whole canvas, with runs of equal cells stored only once.
+    size: Vec2,
+    runs: Vec<(u32, Cell)>,
+}
+
+impl Snapshot {
+    fn new(size: Vec2, cells: &[Cell]) -> Self {
+        let mut runs: Vec<(u32, Cell)> = Vec::new();
+        for cell in cells.iter() {
+            match runs.last_mut() {
+                Some((n, c)) if c == cell => *n += 1,
+                _ => runs.push((1, *cell)),
+            }
+        }
+
+        Snapshot {size, runs}
+    }
+
+    fn unpack(&self) -> Board {
+        let cells = self.runs.iter()
+            .flat_map(|(n, cell)| std::iter::repeat(*cell).take(*n as usize))
+            .collect();
+
+        Board {size: self.size, cells}
+    }
+}
+
+enum Change {
+    // Cells that changed in place, with their index, old and new value.
+    Cells(Vec<(usize, Cell, Cell)>),
+    // Resizes, loads and anything that touches most of the canvas are kept whole on both sides.
+    Whole(Snapshot, Snapshot),
+}
+
+impl Change {
+    fn bytes(&self) -> usize {
+        match self {
+            Change::Cells(changes) => changes.len() * size_of::<(usize, Cell, Cell)>(),
+            Change::Whole(before, after) => (before.runs.len() + after.runs.len()) * size_of::<(u32, Cell)>(),
+        }
+    }
+}
+
+struct Step {
+    change: Change,
+    layers: Option<(Vec<Layer>, Vec<Layer>)>, // Only there if the layers were renamed, added, hidden and so on.
+}
+
+impl Step {
+    fn bytes(&self) -> usize {
+        let layers = self.layers.as_ref().map_or(0, |(before, after)| (before.len() + after.len()) * size_of::<Layer>());
+        self.change.bytes() + layers
+    }
+}
+
+pub struct History {
+    current: Board,
+    layers: Vec<Layer>,
+    past: VecDeque<Step>,
+    future: Vec<Step>,
+    bytes: usize,
+    depth: usize,  // How many steps we remember at most.
+    budget: usize, // How many bytes the steps may take up, the oldest ones are forgotten first.
+}
+
+impl History {
+    pub fn new(size: Vec2, cells: &[Cell], layers: &[Layer]) -> Self {
+        History {
+            current: Board {size, cells: cells.to_vec()},
+            layers: layers.to_vec(),
+            past: VecDeque::new(),
+            future: Vec::new(),
+            bytes: 0,
+            depth: 250,
+            budget: 32 << 20,
+        }
+    }
+
+    pub fn record(&mut self, size: Vec2, cells: &[Cell], layers: &[Layer]) -> bool {
+        // Adds the way from the last recorded state to this one as a step. Returns false if nothing changed.
+        let layers = if layers != self.layers {Some((self.layers.clone(), layers.to_vec()))} else {None};
+
+        let change = if size != self.current.size {
+            Change::Whole(Snapshot::new(self.current.size, &self.current.cells), Snapshot::new(size, cells))
+        }
+        else {
+            let changes: Vec<(usize, Cell, Cell)> = self.current.cells.iter().zip(cells.iter())
+                .enumerate()
+                .filter(|(_i, (old, new))| old != new)
+                .map(|(i, (old, new))| (i, *old, *new))
+                .collect();
+
+            if changes.is_empty() && layers.is_none() {
+                return false;
+            }
+
+            // A big change is often cheaper to keep as two snapshots, think of filling everything with one colour.
+            let diff = Change::Cells(changes);
+            if diff.bytes() < 4096 {
+                diff
+            }
+            else {
+                let whole = Change::Whole(Snapshot::new(self.current.size, &self.current.cells), Snapshot::new(size, cells));
+                if whole.bytes() < diff.bytes() {whole} else {diff}
+            }
+        };
+
+        if let Some((_before, after)) = &layers {
+            self.layers = after.clone();
+        }
+        let step = Step {change, layers};
+
+        self.current = Board {size, cells: cells.to_vec()};
+        self.future.clear();
+        self.bytes += step.bytes();
+        self.past.push_back(step);
+        self.forget();
+
+        true
+    }
+
+    pub fn undo(&mut self) -> Option<(Board, Vec<Layer>)> {
+        let step = self.past.pop_back()?;
+        self.bytes -= step.bytes();
+
+        match &step.change {
+            Change::Cells(changes) => {
+                for (i, old, _new) in changes.iter() {
+                    self.current.cells[*i] = *old;
+                }
+            }
+            Change::Whole(before, _after) => self.current = before.unpack(),
+        }
+        if let Some((before, _after)) = &step.layers {
+            self.layers = before.clone();
+        }
+
+        self.future.push(step);
+        Some((self.current.clone(), self.layers.clone()))
+    }
+
+    pub fn redo(&mut self) -> Option<(Board, Vec<Layer>)> {
+        let step = self.future.pop()?;
+
+        match &step.change {
+            Change::Cells(changes) => {
+                for (i, _old, new) in changes.iter() {
+                    self.current.cells[*i] = *new;
+                }
+            }
+            Change::Whole(_before, after) => self.current = after.unpack(),
+        }
+        if let Some((_before, after)) = &step.layers {
+            self.layers = after.clone();
+        }
+
+        self.bytes += step.bytes();
+        self.past.push_back(step);
+        Some((self.current.clone(), self.layers.clone()))
+    }
+
+    pub fn set_limits(&mut self, depth: usize, budget: usize) {
+        self.depth = depth;
+        self.budget = budget;
+        self.forget();
+    }
+
+    pub fn limits(&self) -> (usize, usize) {
+        (self.depth, self.budget)
+    }
+
+    pub fn usage(&self) -> (usize, usize) {
+        // Steps and bytes that are currently remembered.
+        (self.past.len(), self.bytes)
+    }
+
+    fn forget(&mut self) {
+        // The newest step is always kept, even if it alone is over the budget.
+        while self.past.len() > self.depth || (self.bytes > self.budget && self.past.len() > 1) {
+            if let Some(step) = self.past.pop_front() {
+                self.bytes -= step.bytes();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cursive::theme::Color;
+
+    fn board(size: Vec2, symbol: char) -> Vec<Cell> {
+        vec![Cell {symbol, ..Cell::default()}; size.x * size.y]
+    }
+
+    #[test]
+    fn undo_and_redo_across_cell_and_whole_steps() {
+        let layers = [Layer::new("Background")];
+        let small = Vec2::new(3, 2);
+        let big = Vec2::new(4, 4);
+        let mut history = History::new(small, &board(small, ' '), &layers);
+
+        // One cell changes, then a resize, then a fill of everything.
+        let mut painted = board(small, ' ');
+        painted[4].backcolor = Color::Rgb(1, 2, 3);
+        assert!(history.record(small, &painted, &layers));
+        assert!(!history.record(small, &painted, &layers));
+        assert!(history.record(big, &board(big, ' '), &layers));
+        assert!(history.record(big, &board(big, 'x'), &layers));
+        assert!(matches!(history.past[0].change, Change::Cells(_)));
+        assert!(matches!(history.past[1].change, Change::Whole(..)));
+
+        let (b, _) = history.undo().unwrap();
+        assert!(b.size == big && b.cells == board(big, ' '));
+        let (b, _) = history.undo().unwrap();
+        assert!(b.size == small && b.cells == painted);
+        let (b, _) = history.undo().unwrap();
+        assert!(b.cells == board(small, ' '));
+        assert!(history.undo().is_none());
+
+        history.redo();
+        let (b, _) = history.redo().unwrap();
+        assert!(b.size == big && b.cells == board(big, ' '));
+        let (b, _) = history.redo().unwrap();
+        assert!(b.cells == board(big, 'x'));
+        assert!(history.redo().is_none());
+
+        // Something new after an undo drops what could have been redone.
+        history.undo();
+        history.record(big, &board(big, 'y'), &layers);
+        assert!(history.redo().is_none());
+    }
+
+    #[test]
+    fn layer_changes_are_steps_of_their_own() {
+        let size = Vec2::new(2, 2);
+        let mut history = History::new(size, &board(size, ' '), &[Layer::new("Background")]);
+        let mut renamed = Layer::new("Sky");
+        assert!(history.record(size, &board(size, ' '), std::slice::from_ref(&renamed)));
+        renamed.opacity = 50;
+        assert!(history.record(size, &board(size, ' '), std::slice::from_ref(&renamed)));
+
+        assert!(history.undo().unwrap().1[0].opacity == 100);
+        assert!(history.undo().unwrap().1[0].name == "Background");
+        assert!(history.redo().unwrap().1[0].name == "Sky");
+    }
+
+    #[test]
+    fn limits_forget_the_oldest_steps() {
+        let size = Vec2::new(2, 1);
+        let layers = [Layer::new("Background")];
+        let mut history = History::new(size, &board(size, ' '), &layers);
+        history.set_limits(2, usize::MAX);
+        for c in ['a', 'b', 'c'] {
+            history.record(size, &board(size, c), &layers);
+        }
+
+        assert_eq!(history.usage().0, 2);
+        history.undo();
+        assert!(history.undo().unwrap().0.cells == board(size, 'a'));
+        assert!(history.undo().is_none());
+    }
+}
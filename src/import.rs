@@ -0,0 +1,610 @@
+// Reading other people's art into a board of cells. The counterpart to export.rs.
+
+use std::collections::BTreeMap;
+
+use cursive::theme::Color;
+use cursive::Vec2;
+use image::imageops::FilterType;
+use image::{Rgb, RgbImage};
+use unicode_width::UnicodeWidthChar;
+
+use crate::canvas::{Board, Cell};
+use crate::dither::{self, Dither};
+use crate::font::braille_bit;
+use crate::palette::{xterm_256, XTERM_16};
+
+// Code page 437, the character set of DOS and of most classic ANSI art, from 0x80 up.
+const CP437: &str = "ÇüéâäàåçêëèïîìÄÅÉæÆôöòûùÿÖÜ¢£¥₧ƒáíóúñÑªº¿⌐¬½¼¡«»\
+                     ░▒▓│┤╡╢╖╕╣║╗╝╜╛┐└┴┬├─┼╞╟╚╔╩╦╠═╬╧╨╤╥╙╘╒╓╫╪┘┌█▄▌▐▀\
+                     αßΓπΣσµτΦΘΩδ∞φε∩≡±≥≤⌠⌡÷≈°∙·√ⁿ²■\u{a0}";
+
+// The most cells across or down an import makes, the same as load allows with -size.
+const LIMIT: usize = 2000;
+
+fn sauce_width(bytes: &[u8]) -> Option<usize> {
+    // SAUCE is a 128 byte record at the very end of most DOS art. For character files it says how many columns they were drawn for.
+    let record = bytes.len().checked_sub(128).map(|start| &bytes[start..])?;
+    if !record.starts_with(b"SAUCE00") || record[94] != 1 || record[95] > 2 {
+        return None;
+    }
+
+    let width = u16::from_le_bytes([record[96], record[97]]) as usize;
+    if width > 0 {Some(width)} else {None}
+}
+
+fn decode(bytes: &[u8]) -> (Vec<char>, bool) {
+    // UTF-8 if it is valid UTF-8, CP437 otherwise. The second value says whether it was CP437.
+    match std::str::from_utf8(bytes) {
+        Ok(text) => (text.chars().collect(), false),
+        Err(_) => {
+            let high: Vec<char> = CP437.chars().collect();
+            let chars = bytes.iter()
+                .map(|&b| if b < 0x80 {b as char} else {high[(b - 0x80) as usize]})
+                .collect();
+            (chars, true)
+        }
+    }
+}
+
+struct Pen {
+    // Everything an SGR sequence can change about the next character.
+    color: (u8, u8, u8),
+    backcolor: (u8, u8, u8),
+    base: Option<usize>, // Which of the 8 base colours the character colour is, as bold makes those brighter.
+    bold: bool,
+    reverse: bool,
+}
+
+impl Pen {
+    const DEFAULT_COLOR: (u8, u8, u8) = XTERM_16[7];
+    const DEFAULT_BACKCOLOR: (u8, u8, u8) = XTERM_16[0];
+
+    fn new() -> Self {
+        Pen {color: Pen::DEFAULT_COLOR, backcolor: Pen::DEFAULT_BACKCOLOR, base: None, bold: false, reverse: false}
+    }
+
+    fn cell(&self, symbol: char) -> Cell {
+        let mut color = self.color;
+        if let (Some(i), true) = (self.base, self.bold) {
+            color = XTERM_16[i + 8];
+        }
+        let (fg, bg) = if self.reverse {(self.backcolor, color)} else {(color, self.backcolor)};
+
+        Cell {color: Color::Rgb(fg.0, fg.1, fg.2), backcolor: Color::Rgb(bg.0, bg.1, bg.2), symbol, ..Cell::default()}
+    }
+
+    fn blank() -> Cell {
+        Pen::new().cell(' ')
+    }
+
+    fn apply(&mut self, params: &[u32]) -> Result<(), String> {
+        // Goes through the parameters of one SGR sequence. Returns the first one we don't know, if any.
+        let mut i = 0;
+        if params.is_empty() {
+            *self = Pen::new();
+        }
+
+        while i < params.len() {
+            match params[i] {
+                0 => *self = Pen::new(),
+                1 => self.bold = true,
+                22 => self.bold = false,
+                7 => self.reverse = true,
+                27 => self.reverse = false,
+                2..=6 | 8 | 9 | 21 | 23..=26 | 28 | 29 => {} // Faint, italic, underline, blinking and the like have no place on a canvas.
+                n @ 30..=37 => {self.color = XTERM_16[n as usize - 30]; self.base = Some(n as usize - 30);}
+                n @ 90..=97 => {self.color = XTERM_16[n as usize - 90 + 8]; self.base = None;}
+                n @ 40..=47 => self.backcolor = XTERM_16[n as usize - 40],
+                n @ 100..=107 => self.backcolor = XTERM_16[n as usize - 100 + 8],
+                39 => {self.color = Pen::DEFAULT_COLOR; self.base = None;}
+                49 => self.backcolor = Pen::DEFAULT_BACKCOLOR,
+                n @ (38 | 48) => {
+                    let rgb = match params.get(i + 1) {
+                        Some(5) if i + 2 < params.len() => {
+                            i += 2;
+                            xterm_256(params[i].min(255) as u8)
+                        }
+                        Some(2) if i + 4 < params.len() => {
+                            i += 4;
+                            let c = |v: u32| v.min(255) as u8;
+                            (c(params[i - 2]), c(params[i - 1]), c(params[i]))
+                        }
+                        _ => return Err(format!("{} without a colour", n)),
+                    };
+                    if n == 38 {self.color = rgb; self.base = None;} else {self.backcolor = rgb;}
+                }
+                n => return Err(n.to_string()),
+            }
+            i += 1;
+        }
+
+        Ok(())
+    }
+}
+
+pub fn from_ansi(bytes: &[u8]) -> (Board, Vec<String>) {
+    // Reads text with colour escapes into a board just big enough to hold it.
+    // Sequences we can't make sense of are skipped and listed in the second value, once each.
+    let (mut chars, cp437) = decode(bytes);
+    let end = chars.iter().position(|&c| c == '\x1a');
+    if let Some(end) = end {
+        chars.truncate(end); // The end-of-file marker, after it there's only SAUCE metadata.
+    }
+
+    // DOS art wraps at the width SAUCE gives, or else at 80 columns. Terminal output doesn't wrap.
+    // Without SAUCE, anything that isn't UTF-8 or has an end-of-file marker is taken to be from DOS, even if it's plain ASCII.
+    let wrap = match sauce_width(bytes) {
+        Some(width) => width,
+        None if cp437 || end.is_some() => 80,
+        None => usize::MAX,
+    };
+
+    let mut rows: Vec<Vec<Cell>> = Vec::new();
+    let mut pen = Pen::new();
+    let mut problems: BTreeMap<String, usize> = BTreeMap::new();
+    let (mut x, mut y) = (0usize, 0usize);
+    let mut saved = (0, 0);
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        i += 1;
+
+        match c {
+            '\x1b' if chars.get(i) == Some(&'[') => {
+                // A control sequence: parameters, maybe some intermediate bytes, and a final letter.
+                let start = i - 1;
+                i += 1;
+                while i < chars.len() && !('\x40'..='\x7e').contains(&chars[i]) {
+                    i += 1;
+                }
+                if i >= chars.len() {
+                    *problems.entry("an escape sequence cut off at the end".to_string()).or_insert(0) += 1;
+                    break;
+                }
+
+                let raw: String = chars[start + 2..i].iter().collect();
+                let last = chars[i];
+                i += 1;
+
+                let params: Option<Vec<u32>> = if raw.is_empty() {
+                    Some(Vec::new())
+                } else {
+                    raw.split(';').map(|p| if p.is_empty() {Some(0)} else {p.parse::<u32>().ok()}).collect()
+                };
+                let n = |default: u32| params.as_ref().and_then(|p| p.first().copied()).filter(|&v| v > 0).unwrap_or(default) as usize;
+
+                match (last, &params) {
+                    ('m', Some(p)) => {
+                        if let Err(unknown) = pen.apply(p) {
+                            *problems.entry(format!("colour code {}", unknown)).or_insert(0) += 1;
+                        }
+                    }
+                    ('C', Some(_)) => x += n(1),
+                    ('D', Some(_)) => x = x.saturating_sub(n(1)),
+                    ('A', Some(_)) => y = y.saturating_sub(n(1)),
+                    ('B', Some(_)) => y += n(1),
+                    ('H', Some(p)) | ('f', Some(p)) => {
+                        y = p.first().copied().unwrap_or(1).max(1) as usize - 1;
+                        x = p.get(1).copied().unwrap_or(1).max(1) as usize - 1;
+                    }
+                    ('s', Some(_)) => saved = (x, y),
+                    ('u', Some(_)) => (x, y) = saved,
+                    _ => *problems.entry(format!("ESC[{}{}", raw, last)).or_insert(0) += 1,
+                }
+            }
+            '\x1b' => {
+                *problems.entry(format!("ESC {}", chars.get(i).map(|c| c.escape_debug().to_string()).unwrap_or_default())).or_insert(0) += 1;
+                i += 1;
+            }
+            '\n' => {
+                x = 0;
+                y += 1;
+            }
+            '\r' => x = 0,
+            '\t' => x = (x / 8 + 1) * 8,
+            c if c.is_control() => {}
+            c => {
+                if x >= wrap {
+                    x = 0;
+                    y += 1;
+                }
+                if x >= LIMIT || y >= LIMIT {
+                    *problems.entry(format!("text outside of {}x{} cells", LIMIT, LIMIT)).or_insert(0) += 1;
+                    continue;
+                }
+                while rows.len() <= y {
+                    rows.push(Vec::new());
+                }
+                let row = &mut rows[y];
+                while row.len() <= x {
+                    row.push(Pen::blank());
+                }
+                row[x] = pen.cell(c);
+                x += 1;
+
+                // A wide character takes the next cell too, as a blank in the same colours, like from_text does it.
+                if is_wide(c) {
+                    if row.len() <= x {
+                        row.push(Pen::blank());
+                    }
+                    row[x] = pen.cell(' ');
+                    x += 1;
+                }
+            }
+        }
+    }
+
+    let width = rows.iter().map(|r| r.len()).max().unwrap_or(0).clamp(1, LIMIT);
+    let height = rows.len().clamp(1, LIMIT);
+    let mut cells = Vec::with_capacity(width * height);
+    for y in 0..height {
+        for x in 0..width {
+            cells.push(rows.get(y).and_then(|r| r.get(x)).copied().unwrap_or_else(Pen::blank));
+        }
+    }
+
+    let problems = problems.into_iter()
+        .map(|(p, n)| if n > 1 {format!("{} ({} times)", p, n)} else {p})
+        .collect();
+
+    (Board {size: Vec2::new(width, height), cells}, problems)
+}
+
+pub fn from_text(text: &str, pen: Cell) -> Board {
+    // Plain text, one line per row, as wide as the longest line. Every character takes the colours of pen.
+    // A wide character takes up two cells: itself and a blank one after it, the way totext writes it back.
+    let blank = Cell {symbol: ' ', ..pen};
+    let mut rows: Vec<Vec<Cell>> = Vec::new();
+
+    for line in text.lines() {
+        let mut row: Vec<Cell> = Vec::new();
+        for c in line.chars() {
+            match c {
+                '\t' => {
+                    let stop = (row.len() / 8 + 1) * 8;
+                    row.resize(stop, blank);
+                }
+                c => match c.width() {
+                    Some(w) if w > 0 => {
+                        row.push(Cell {symbol: c, ..pen});
+                        if w > 1 {
+                            row.push(blank);
+                        }
+                    }
+                    _ => {} // Control characters and combining marks have no cell of their own.
+                },
+            }
+        }
+        row.truncate(9999);
+        rows.push(row);
+    }
+    rows.truncate(9999);
+
+    let width = rows.iter().map(|r| r.len()).max().unwrap_or(0).max(1);
+    let height = rows.len().max(1);
+    let mut cells = Vec::with_capacity(width * height);
+    for y in 0..height {
+        for x in 0..width {
+            cells.push(rows.get(y).and_then(|r| r.get(x)).copied().unwrap_or(blank));
+        }
+    }
+
+    Board {size: Vec2::new(width, height), cells}
+}
+
+pub fn is_wide(symbol: char) -> bool {
+    symbol.width().unwrap_or(0) > 1
+}
+
+pub struct ImageOptions {
+    pub mode: ImageMode,
+    pub colors: Option<Vec<(u8, u8, u8)>>, // Brings the picture down to these colours first, if set.
+    pub dither: Dither,
+    pub width: Option<usize>,  // The size to fit the picture into, in cells. With neither given the canvas decides.
+    pub height: Option<usize>,
+    pub filter: FilterType,
+    pub aspect: f32, // How many times taller than wide a cell is on screen.
+    pub crop: bool,  // Fills the whole size and cuts off what sticks out, instead of fitting all of the picture in.
+}
+
+impl Default for ImageOptions {
+    fn default() -> Self {
+        ImageOptions {
+            mode: ImageMode::Background,
+            colors: None,
+            dither: Dither::None,
+            width: None,
+            height: None,
+            filter: FilterType::Triangle,
+            aspect: 2.0,
+            crop: false,
+        }
+    }
+}
+
+pub fn scale(img: &RgbImage, options: &ImageOptions, size: (Option<usize>, Option<usize>)) -> RgbImage {
+    // Resizes the picture for the width and height in cells it is going into, keeping its proportions on screen.
+    let (px, py) = options.mode.pixels_per_cell();
+    let (w, h) = (img.width().max(1) as f32, img.height().max(1) as f32);
+
+    // A pixel of the result is 1 / px cells wide and aspect / py cells high, this is how many rows that makes per column.
+    let squash = py as f32 / (px as f32 * options.aspect);
+    let columns = size.0.map(|c| (c * px as usize) as f32);
+    let rows = size.1.map(|r| (r * py as usize) as f32);
+
+    let by_width = columns.map(|c| c / w);
+    let by_height = rows.map(|r| r / (h * squash));
+    let factor = match (by_width, by_height) {
+        (Some(a), Some(b)) if options.crop => a.max(b),
+        (Some(a), Some(b)) => a.min(b),
+        (Some(a), None) | (None, Some(a)) => a,
+        (None, None) => 1.,
+    };
+
+    let new_w = ((w * factor).round() as u32).clamp(1, 9999 * px);
+    let new_h = ((h * factor * squash).round() as u32).clamp(1, 9999 * py);
+    let mut scaled = image::imageops::resize(img, new_w, new_h, options.filter);
+
+    if options.crop {
+        let keep_w = columns.map(|c| (c as u32).min(new_w)).unwrap_or(new_w);
+        let keep_h = rows.map(|r| (r as u32).min(new_h)).unwrap_or(new_h);
+        scaled = image::imageops::crop(&mut scaled, (new_w - keep_w) / 2, (new_h - keep_h) / 2, keep_w, keep_h).to_image();
+    }
+
+    scaled
+}
+
+pub enum ImageMode {
+    // Only background colours, two rows of pixels to a cell.
+    Background,
+    // ▀ in the colour of the upper pixel on the lower one.
+    HalfBlocks,
+    // Symbols that get denser the darker the picture is.
+    Ascii(AsciiOptions),
+    // Braille dots for the dark parts, 2x4 of them to a cell.
+    Braille(BrailleOptions),
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Ink {
+    Original, // The symbol takes the colour of the picture, on white.
+    Mono,     // Black on white.
+    Inverted, // The picture as background, the symbol in the opposite colour.
+}
+
+impl ImageMode {
+    fn pixels_per_cell(&self) -> (u32, u32) {
+        match self {
+            ImageMode::Braille(_) => (2, 4),
+            _ => (1, 2),
+        }
+    }
+}
+
+pub struct AsciiOptions {
+    pub ramp: Vec<char>, // From lightest to densest.
+    pub ink: Ink,
+}
+
+impl Default for AsciiOptions {
+    fn default() -> Self {
+        AsciiOptions {ramp: " .:-=+*#%@".chars().collect(), ink: Ink::Original}
+    }
+}
+
+pub fn ascii(img: &RgbImage, options: &AsciiOptions) -> Board {
+    // One cell for every two rows of pixels, like fromimage does it, with a symbol for how dark the two are together.
+    let (w, h) = (img.width().max(1), img.height().max(1));
+    let rows = (h / 2).max(1);
+    let mut cells = Vec::with_capacity((w * rows) as usize);
+    let last = options.ramp.len().saturating_sub(1);
+
+    for y in 0..rows {
+        for x in 0..w {
+            let top = img.get_pixel(x, 2 * y);
+            let bottom = img.get_pixel(x, (2 * y + 1).min(h - 1));
+            let mean = |i: usize| ((top[i] as u16 + bottom[i] as u16) / 2) as u8;
+            let (r, g, b) = (mean(0), mean(1), mean(2));
+
+            let luminance = 0.2126 * r as f32 + 0.7152 * g as f32 + 0.0722 * b as f32;
+            let step = ((1. - luminance / 255.) * last as f32).round() as usize;
+            let symbol = options.ramp.get(step).copied().unwrap_or(' ');
+
+            let (color, backcolor) = match options.ink {
+                Ink::Original => (Color::Rgb(r, g, b), Color::Rgb(255, 255, 255)),
+                Ink::Mono => (Color::Rgb(0, 0, 0), Color::Rgb(255, 255, 255)),
+                Ink::Inverted => (Color::Rgb(255 - r, 255 - g, 255 - b), Color::Rgb(r, g, b)),
+            };
+            cells.push(Cell {color, backcolor, symbol, ..Cell::default()});
+        }
+    }
+
+    Board {size: Vec2::new(w as usize, rows as usize), cells}
+}
+
+pub struct BrailleOptions {
+    pub threshold: u8, // Pixels darker than this become dots.
+    pub dither: bool,  // Spreads what gets lost at the threshold onto the pixels around, so shades come out as dot patterns.
+}
+
+impl Default for BrailleOptions {
+    fn default() -> Self {
+        BrailleOptions {threshold: 128, dither: false}
+    }
+}
+
+pub fn braille(img: &RgbImage, options: &BrailleOptions) -> Board {
+    // Every cell is a braille character for a block of 2x4 pixels, in the average colour of the pixels that became dots.
+    let (w, h) = (img.width().max(1) as usize, img.height().max(1) as usize);
+
+    // Pixels darker than the threshold become dots. The greys are shifted so the threshold lands in the middle,
+    // then brought down to black and white like any other picture.
+    let shift = 128. - options.threshold as f32;
+    let mut grey = RgbImage::from_fn(w as u32, h as u32, |x, y| {
+        let p = img.get_pixel(x, y);
+        let v = (0.2126 * p[0] as f32 + 0.7152 * p[1] as f32 + 0.0722 * p[2] as f32 + shift).round().clamp(0., 255.) as u8;
+        Rgb([v, v, v])
+    });
+    let dither = if options.dither {Dither::FloydSteinberg} else {Dither::None};
+    dither::quantize(&mut grey, &[(0, 0, 0), (255, 255, 255)], dither);
+    let dark: Vec<bool> = grey.pixels().map(|p| p[0] == 0).collect();
+
+    let (columns, rows) = (w.div_ceil(2), h.div_ceil(4));
+    let mut cells = Vec::with_capacity(columns * rows);
+    for cy in 0..rows {
+        for cx in 0..columns {
+            let mut dots = 0;
+            let mut sum = [0u32; 3];
+            let mut n = 0;
+
+            for (dx, dy) in (0..2).flat_map(|dx| (0..4).map(move |dy| (dx, dy))) {
+                let (x, y) = (cx * 2 + dx, cy * 4 + dy);
+                if x < w && y < h && dark[y * w + x] {
+                    dots |= braille_bit(dx, dy);
+                    let p = img.get_pixel(x as u32, y as u32);
+                    for c in 0..3 {
+                        sum[c] += p[c] as u32;
+                    }
+                    n += 1;
+                }
+            }
+
+            let mean = |c: usize| sum[c].checked_div(n).unwrap_or(0) as u8;
+            let color = Color::Rgb(mean(0), mean(1), mean(2));
+            let symbol = if dots == 0 {' '} else {char::from_u32(0x2800 + dots).unwrap_or(' ')};
+            cells.push(Cell {color, backcolor: Color::Rgb(255, 255, 255), symbol, ..Cell::default()});
+        }
+    }
+
+    Board {size: Vec2::new(columns, rows), cells}
+}
+
+pub fn backgrounds(img: &RgbImage) -> Board {
+    // What fromimage does: one cell for every two rows of pixels, coloured after the upper one.
+    let (w, h) = (img.width().max(1), img.height().max(1));
+    let rows = (h / 2).max(1);
+    let mut cells = Vec::with_capacity((w * rows) as usize);
+
+    for y in 0..rows {
+        for x in 0..w {
+            let p = img.get_pixel(x, 2 * y);
+            cells.push(Cell {backcolor: Color::Rgb(p[0], p[1], p[2]), ..Cell::default()});
+        }
+    }
+
+    Board {size: Vec2::new(w as usize, rows as usize), cells}
+}
+
+pub fn half_blocks(img: &RgbImage) -> Board {
+    // One cell for every two rows of pixels, drawn as ▀ in the colour of the upper one on the lower one.
+    // An odd last row gets the same colour top and bottom.
+    let (w, h) = (img.width().max(1), img.height().max(1));
+    let mut cells = Vec::with_capacity((w * h.div_ceil(2)) as usize);
+
+    for y in 0..h.div_ceil(2) {
+        for x in 0..w {
+            let top = img.get_pixel(x, 2 * y);
+            let bottom = img.get_pixel(x, (2 * y + 1).min(h - 1));
+            cells.push(Cell {
+                color: Color::Rgb(top[0], top[1], top[2]),
+                backcolor: Color::Rgb(bottom[0], bottom[1], bottom[2]),
+                symbol: '▀',
+                ..Cell::default()
+            });
+        }
+    }
+
+    Board {size: Vec2::new(w as usize, h.div_ceil(2) as usize), cells}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rgb(c: (u8, u8, u8)) -> Color {
+        Color::Rgb(c.0, c.1, c.2)
+    }
+
+    #[test]
+    fn ansi_cursor_and_colours() {
+        let (board, problems) = from_ansi(b"\x1b[31;44mab\x1b[2;4Hc\x1b[1Dd\x1b[0;1;32me\x1b[7mf\x1b[5q");
+        assert_eq!(board.size, Vec2::new(6, 2));
+        assert_eq!(problems, vec!["ESC[5q".to_string()]);
+
+        let at = |x: usize, y: usize| board.cells[x + y * board.size.x];
+        assert!(at(0, 0).symbol == 'a' && at(0, 0).color == rgb(XTERM_16[1]) && at(0, 0).backcolor == rgb(XTERM_16[4]));
+        assert!(at(2, 0).symbol == ' ' && at(2, 0).backcolor == rgb(XTERM_16[0]));
+        // The cursor moved back over the c, so d took its place.
+        assert_eq!((at(3, 1).symbol, at(4, 1).symbol), ('d', 'e'));
+        assert!(at(4, 1).color == rgb(XTERM_16[10]) && at(4, 1).backcolor == rgb(XTERM_16[0]));
+        assert!(at(5, 1).symbol == 'f' && at(5, 1).color == rgb(XTERM_16[0]) && at(5, 1).backcolor == rgb(XTERM_16[10]));
+    }
+
+    #[test]
+    fn ansi_far_cursor_moves_stay_small() {
+        let (board, problems) = from_ansi(b"\x1b[9998;9998H x");
+        assert_eq!(board.size, Vec2::new(1, 1));
+        assert_eq!(problems, vec!["text outside of 2000x2000 cells (2 times)".to_string()]);
+    }
+
+    #[test]
+    fn ansi_wide_characters_take_two_cells() {
+        let (board, _) = from_ansi("\x1b[31m描x\n描\x1b[1Dy".as_bytes());
+        assert_eq!(board.size, Vec2::new(3, 2));
+
+        let row: String = board.cells.iter().map(|c| c.symbol).collect();
+        assert_eq!(row, "描 x描y ");
+        assert!(board.cells[1].color == rgb(XTERM_16[1]));
+    }
+
+    #[test]
+    fn text_tabs_and_wide_characters() {
+        let pen = Cell {color: Color::Rgb(1, 2, 3), ..Cell::default()};
+        let board = from_text("a\tb\n描x\u{301}\n", pen);
+        assert_eq!(board.size, Vec2::new(9, 2));
+
+        let row: String = board.cells.iter().map(|c| c.symbol).collect();
+        assert_eq!(row, "a       b描 x      ");
+        assert!(board.cells.iter().all(|c| c.color == pen.color));
+    }
+
+    #[test]
+    fn braille_threshold_and_dither() {
+        // The left column dark, the right one light: dots 1, 2, 3 and 7.
+        let img = RgbImage::from_fn(2, 4, |x, _| if x == 0 {Rgb([100, 100, 100])} else {Rgb([200, 200, 200])});
+        let board = braille(&img, &BrailleOptions {threshold: 150, dither: false});
+        assert_eq!(board.size, Vec2::new(1, 1));
+        assert_eq!(board.cells[0].symbol, '\u{2847}');
+        assert!(board.cells[0].color == Color::Rgb(100, 100, 100));
+
+        assert_eq!(braille(&img, &BrailleOptions {threshold: 100, dither: false}).cells[0].symbol, ' ');
+
+        // Middle grey comes out as every other dot or so.
+        let img = RgbImage::from_pixel(8, 8, Rgb([128, 128, 128]));
+        let board = braille(&img, &BrailleOptions {threshold: 128, dither: true});
+        let dots: u32 = board.cells.iter().map(|c| (c.symbol as u32).saturating_sub(0x2800).count_ones()).sum();
+        assert!((24..=40).contains(&dots), "{} dots", dots);
+    }
+
+    #[test]
+    fn ansi_dos_art_wraps() {
+        let mut bytes = vec![b'x'; 100];
+        let (board, _) = from_ansi(&bytes);
+        assert_eq!(board.size, Vec2::new(100, 1));
+
+        // Plain ASCII with an end-of-file marker wraps at 80, with SAUCE at the width it gives.
+        bytes.push(0x1a);
+        let (board, _) = from_ansi(&bytes);
+        assert_eq!(board.size, Vec2::new(80, 2));
+
+        let mut sauce = b"SAUCE00".to_vec();
+        sauce.resize(128, 0);
+        sauce[94] = 1;
+        sauce[95] = 1;
+        sauce[96] = 40;
+        bytes.extend(sauce);
+        let (board, _) = from_ansi(&bytes);
+        assert_eq!(board.size, Vec2::new(40, 3));
+    }
+}
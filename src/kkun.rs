@@ -0,0 +1,491 @@
+// Reading and writing kakikun projects, the .kkun files.
+//
+// Version 3 looks like this:
+//
+//     KKUN 3
+//     size 54x37
+//     software kakikun 0.1.0
+//     author fi-le
+//
+//     layer Background
+//     cells
+//      ffffff3d3a2dff ffffff454233ff ffffff44423...
+//
+//     layer Outlines
+//     opacity 50
+//     blend multiply
+//     visible no
+//     locked yes
+//     cells
+//     ...
+//
+// The first line names the format and its version. Then come metadata lines, each a key, a space and a value.
+// The only key that has to be there is size, the width and height in cells. Blank lines and lines starting with # are skipped.
+// A line "layer" followed by the layer's name ends the metadata and starts the first layer, from the bottom up. Its settings follow
+// in the same key and value way: opacity in percent, the blend mode by name, visible and locked as yes or no.
+// Anything left out keeps its default.
+// A line saying "cells" ends the settings. After it come exactly height lines, one for each row of the layer, with exactly width cells each.
+// A cell is its symbol followed by fourteen hex digits: the character colour and the background colour, both as rrggbb,
+// and then how much the background covers the layers below, from 00 for not at all to ff.
+// A symbol that is a backslash or a control character is written as a backslash and the six hex digits of its code point instead.
+// A cell without a symbol has a backslash and six dashes in its place, one without a character colour six dashes instead of it.
+// So a cell with nothing at all in it looks like \------------ffffff00.
+//
+// Version 2 is the same with a single layer and without the layer line, "cells" directly follows the metadata.
+// Its cells have twelve hex digits, the alpha is left out.
+//
+// Version 1 files start with a line "RGB", then the size as 0000x0000, then one line with all cells, each starting with a tab
+// and looking like rrrgggbbb|rrrgggbbb|s with the colours in decimal. We still read those and version 2, but only write version 3.
+
+use std::fmt;
+use std::fmt::Write;
+
+use cursive::theme::Color;
+use cursive::Vec2;
+
+use crate::blend::Blend;
+use crate::canvas::{Cell, Layer};
+use crate::palette::to_rgb;
+
+pub struct Project {
+    pub size: Vec2,
+    pub layers: Vec<(Layer, Vec<Cell>)>,
+    pub meta: Vec<(String, String)>,
+}
+
+pub struct ParseError {
+    // Lines and columns count from 1, columns in characters rather than bytes.
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}, column {}: {}", self.line, self.column, self.message)
+    }
+}
+
+pub fn reserved(key: &str) -> bool {
+    // Keys the format itself uses, metadata can't have them or the file wouldn't read back.
+    matches!(key, "" | "size" | "software" | "layer" | "cells") || key.starts_with('#')
+}
+
+fn error<T>(line: usize, column: usize, message: String) -> Result<T, ParseError> {
+    Err(ParseError {line, column, message})
+}
+
+pub fn write(size: Vec2, layers: &[(&Layer, &[Cell])], meta: &[(String, String)]) -> String {
+    let mut text = String::with_capacity(64 + layers.len() * (64 + size.x * size.y * 16));
+
+    text.push_str("KKUN 3\n");
+    let _ = writeln!(text, "size {}x{}", size.x, size.y);
+    let _ = writeln!(text, "software kakikun {}", env!("CARGO_PKG_VERSION"));
+    for (key, value) in meta.iter().filter(|(k, _)| !reserved(k)) {
+        let _ = writeln!(text, "{} {}", key, value);
+    }
+
+    for (layer, cells) in layers.iter() {
+        let _ = writeln!(text, "\nlayer {}", layer.name);
+        if layer.opacity != 100 {
+            let _ = writeln!(text, "opacity {}", layer.opacity);
+        }
+        if layer.blend != Blend::Normal {
+            let _ = writeln!(text, "blend {}", layer.blend.name());
+        }
+        if !layer.visible {
+            text.push_str("visible no\n");
+        }
+        if layer.locked {
+            text.push_str("locked yes\n");
+        }
+        text.push_str("cells\n");
+
+        for (i, cell) in cells.iter().enumerate() {
+            if cell.no_symbol {
+                text.push_str("\\------");
+            }
+            else if cell.symbol == '\\' || cell.symbol.is_control() {
+                let _ = write!(text, "\\{:06x}", cell.symbol as u32);
+            }
+            else {
+                text.push(cell.symbol);
+            }
+
+            if cell.no_color {
+                text.push_str("------");
+            }
+            else {
+                let (r, g, b) = to_rgb(cell.color);
+                let _ = write!(text, "{:02x}{:02x}{:02x}", r, g, b);
+            }
+            let (r, g, b) = to_rgb(cell.backcolor);
+            let _ = write!(text, "{:02x}{:02x}{:02x}{:02x}", r, g, b, cell.alpha);
+
+            if i % size.x == size.x - 1 {
+                text.push('\n');
+            }
+        }
+    }
+
+    text
+}
+
+pub fn read(text: &str) -> Result<Project, ParseError> {
+    let first = text.lines().next().unwrap_or("");
+
+    if first.trim_end() == "RGB" {
+        read_v1(text)
+    }
+    else if first.trim_end() == "KKUN 2" {
+        read_v2(text, 2)
+    }
+    else if first.trim_end() == "KKUN 3" {
+        read_v2(text, 3)
+    }
+    else if let Some(version) = first.strip_prefix("KKUN ") {
+        error(1, 6, format!("version {} is newer than this kakikun understands", version.trim()))
+    }
+    else {
+        error(1, 1, "this is not a kakikun project".to_string())
+    }
+}
+
+fn parse_size(text: &str, line: usize, column: usize) -> Result<Vec2, ParseError> {
+    let parts: Vec<&str> = text.trim().split('x').collect();
+    if parts.len() != 2 {
+        return error(line, column, format!("expected a size like 50x25, found '{}'", text.trim()));
+    }
+
+    match (parts[0].parse::<usize>(), parts[1].parse::<usize>()) {
+        (Ok(w), Ok(h)) if w > 0 && h > 0 && w <= 9999 && h <= 9999 => Ok(Vec2::new(w, h)),
+        _ => error(line, column, format!("'{}' is not a size between 1x1 and 9999x9999", text.trim())),
+    }
+}
+
+fn hex(chars: &[char], at: usize, digits: usize, line: usize) -> Result<u32, ParseError> {
+    // Reads a fixed number of hex digits starting at the character index at.
+    let found: String = chars.iter().skip(at).take(digits).collect();
+    if found.chars().count() < digits {
+        return error(line, at + 1, "the row ends in the middle of a cell".to_string());
+    }
+
+    u32::from_str_radix(&found, 16).or_else(|_| error(line, at + 1, format!("expected {} hex digits, found '{}'", digits, found)))
+}
+
+fn parse_yes(value: &str, line: usize, column: usize) -> Result<bool, ParseError> {
+    match value {
+        "yes" => Ok(true),
+        "no" => Ok(false),
+        _ => error(line, column, format!("expected yes or no, found '{}'", value)),
+    }
+}
+
+fn read_v2(text: &str, version: u8) -> Result<Project, ParseError> {
+    let lines: Vec<&str> = text.lines().collect();
+    let mut meta: Vec<(String, String)> = Vec::new();
+    let mut size: Option<Vec2> = None;
+    let mut n = 1;
+
+    // Version 2 has one layer without a name, version 3 names every layer before its cells.
+    let mut next = loop {
+        let line = match lines.get(n) {
+            Some(l) => l.trim_end(),
+            None => return error(n + 1, 1, "the file ends before the cells start".to_string()),
+        };
+        n += 1;
+
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if version == 2 && line == "cells" {
+            break Some(Layer::new("Background"));
+        }
+        if version >= 3 && (line == "layer" || line.starts_with("layer ")) {
+            break Some(Layer::new(line[5..].trim()));
+        }
+
+        let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+        if key == "size" {
+            size = Some(parse_size(value, n, key.len() + 2)?);
+        }
+        else if key != "software" {
+            meta.push((key.to_string(), value.to_string()));
+        }
+    };
+
+    let size = match size {
+        Some(s) => s,
+        None => return error(n, 1, "the size is missing, it has to come before the cells".to_string()),
+    };
+
+    let mut layers = Vec::new();
+    while let Some(mut layer) = next.take() {
+        if version >= 3 {
+            loop {
+                let line = match lines.get(n) {
+                    Some(l) => l.trim_end(),
+                    None => return error(n + 1, 1, format!("the file ends before the cells of layer '{}' start", layer.name)),
+                };
+                n += 1;
+
+                if line.is_empty() || line.starts_with('#') {
+                    continue;
+                }
+                if line == "cells" {
+                    break;
+                }
+
+                let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+                let column = key.len() + 2;
+                match key {
+                    "opacity" => layer.opacity = match value.parse::<u8>() {
+                        Ok(o) if o <= 100 => o,
+                        _ => return error(n, column, format!("expected an opacity from 0 to 100, found '{}'", value)),
+                    },
+                    "blend" => layer.blend = match Blend::parse(value) {
+                        Some(b) => b,
+                        None => return error(n, column, format!("'{}' is not a blend mode", value)),
+                    },
+                    "visible" => layer.visible = parse_yes(value, n, column)?,
+                    "locked" => layer.locked = parse_yes(value, n, column)?,
+                    _ => return error(n, 1, format!("'{}' is not a layer setting", key)),
+                }
+            }
+        }
+
+        layers.push((layer, read_cells(&lines, n, size, version >= 3)?));
+        n += size.y;
+
+        // After the rows, only another layer or the end of the file may follow. Version 2 ignores anything after its rows.
+        while let Some(line) = lines.get(n).map(|l| l.trim_end()).filter(|_| version >= 3) {
+            n += 1;
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if line == "layer" || line.starts_with("layer ") {
+                next = Some(Layer::new(line[5..].trim()));
+                break;
+            }
+            return error(n, 1, format!("expected {} rows of cells or another layer", size.y));
+        }
+    }
+
+    Ok(Project {size, layers, meta})
+}
+
+fn read_cells(lines: &[&str], n: usize, size: Vec2, transparent: bool) -> Result<Vec<Cell>, ParseError> {
+    // Reads the rows of one layer, starting with the line at index n. Only transparent cells have an alpha and dashes.
+    let dashes = |row: &[char], at: usize| transparent && row.iter().skip(at).take(6).filter(|c| **c == '-').count() == 6;
+
+    // Grown row by row, the size alone could ask for gigabytes in a file of three lines.
+    let mut cells = Vec::new();
+    for y in 0..size.y {
+        let line = n + y + 1;
+        let row: Vec<char> = match lines.get(n + y) {
+            Some(l) => l.trim_end_matches('\r').chars().collect(),
+            None => return error(line, 1, format!("expected {} rows of cells, but there are only {}", size.y, y)),
+        };
+        cells.reserve(size.x);
+
+        let mut at = 0;
+        for x in 0..size.x {
+            let no_symbol = row.get(at) == Some(&'\\') && dashes(&row, at + 1);
+            let symbol = match row.get(at) {
+                Some('\\') if no_symbol => {
+                    at += 7;
+                    ' '
+                }
+                Some('\\') => {
+                    let code = hex(&row, at + 1, 6, line)?;
+                    at += 7;
+                    match char::from_u32(code) {
+                        Some(c) => c,
+                        None => return error(line, at - 6, format!("{:x} is not a valid character", code)),
+                    }
+                }
+                Some(c) => {
+                    at += 1;
+                    *c
+                }
+                None => return error(line, at + 1, format!("expected {} cells in this row, found {}", size.x, x)),
+            };
+
+            let no_color = dashes(&row, at);
+            let color = if no_color {0xffffff} else {hex(&row, at, 6, line)?};
+            let backcolor = hex(&row, at + 6, 6, line)?;
+            at += 12;
+            let alpha = if transparent {
+                at += 2;
+                hex(&row, at - 2, 2, line)? as u8
+            }
+            else {
+                255
+            };
+
+            let rgb = |c: u32| Color::Rgb((c >> 16) as u8, (c >> 8) as u8, c as u8);
+            cells.push(Cell {color: rgb(color), backcolor: rgb(backcolor), symbol, alpha, no_symbol, no_color});
+        }
+
+        if row.len() > at && row[at..].iter().any(|c| !c.is_whitespace()) {
+            return error(line, at + 1, format!("there are more than {} cells in this row", size.x));
+        }
+    }
+
+    Ok(cells)
+}
+
+fn read_v1(text: &str) -> Result<Project, ParseError> {
+    let lines: Vec<&str> = text.lines().collect();
+    let size_line = lines.get(1).map(|l| l.trim_end()).unwrap_or("");
+    let size = parse_size(size_line, 2, 1)?;
+
+    let row: Vec<char> = match lines.get(2) {
+        Some(l) => l.chars().collect(),
+        None => return error(3, 1, "the cells are missing".to_string()),
+    };
+
+    // Every cell starts with a tab, so we go from tab to tab and remember where we are for error messages.
+    // A cell takes at least 20 characters, which bounds how many there can be no matter what the size says.
+    let mut cells = Vec::with_capacity((size.x * size.y).min(row.len() / 20));
+    let mut at = 0;
+    while at < row.len() && cells.len() < size.x * size.y {
+        if row[at] != '\t' {
+            return error(3, at + 1, "expected a tab before the next cell".to_string());
+        }
+        at += 1;
+
+        let number = |from: usize| -> Result<u8, ParseError> {
+            let found: String = row.iter().skip(from).take(3).collect();
+            found.parse::<u8>().or_else(|_| error(3, from + 1, format!("expected a colour value from 000 to 255, found '{}'", found)))
+        };
+        let bar = |from: usize| -> Result<(), ParseError> {
+            match row.get(from) {
+                Some('|') => Ok(()),
+                _ => error(3, from + 1, "expected a | between the parts of a cell".to_string()),
+            }
+        };
+
+        let (r, g, b) = (number(at)?, number(at + 3)?, number(at + 6)?);
+        bar(at + 9)?;
+        let (r2, g2, b2) = (number(at + 10)?, number(at + 13)?, number(at + 16)?);
+        bar(at + 19)?;
+
+        // Older files may leave out a blank symbol entirely, in which case the next tab follows right away.
+        let symbol = match row.get(at + 20) {
+            Some('\t') | None => {at += 20; ' '},
+            Some(c) => {at += 21; *c},
+        };
+
+        cells.push(Cell {color: Color::Rgb(r, g, b), backcolor: Color::Rgb(r2, g2, b2), symbol, ..Cell::default()});
+    }
+
+    if cells.len() < size.x * size.y {
+        return error(3, at + 1, format!("expected {} cells, found {}", size.x * size.y, cells.len()));
+    }
+
+    Ok(Project {size, layers: vec![(Layer::new("Background"), cells)], meta: Vec::new()})
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn project(text: &str) -> Project {
+        match read(text) {
+            Ok(p) => p,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    #[test]
+    fn reserved_metadata_is_not_written() {
+        let layer = Layer::new("Background");
+        let cells = vec![Cell::default(); 2];
+        let meta = vec![
+            ("author".to_string(), "fi-le".to_string()),
+            ("layer".to_string(), "foo".to_string()),
+            ("cells".to_string(), "bar".to_string()),
+            ("#note".to_string(), "baz".to_string()),
+        ];
+
+        let text = write(Vec2::new(2, 1), &[(&layer, &cells)], &meta);
+        let p = project(&text);
+        assert_eq!(p.meta, vec![("author".to_string(), "fi-le".to_string())]);
+        assert_eq!(p.layers.len(), 1);
+        assert!(reserved("layer") && reserved("size") && reserved("#x") && !reserved("author"));
+    }
+
+    fn fails_at(text: &str, line: usize, column: usize) {
+        match read(text) {
+            Ok(_) => panic!("read a broken file"),
+            Err(e) => assert_eq!((e.line, e.column), (line, column), "{}", e),
+        }
+    }
+
+    #[test]
+    fn v3_round_trip() {
+        let mut top = Layer::new("Outlines");
+        top.opacity = 50;
+        top.blend = Blend::Multiply;
+        top.visible = false;
+        top.locked = true;
+
+        let bottom = vec![
+            Cell {symbol: '\\', color: Color::Rgb(1, 2, 3), ..Cell::default()},
+            Cell {symbol: '描', backcolor: Color::Rgb(250, 0, 16), ..Cell::default()},
+            Cell {symbol: '\t', ..Cell::default()},
+        ];
+        let above = vec![Cell::CLEAR, Cell {symbol: 'x', alpha: 128, no_color: true, ..Cell::default()}, Cell::CLEAR];
+        let meta = vec![("author".to_string(), "fi-le".to_string())];
+
+        let text = write(Vec2::new(3, 1), &[(&Layer::new("Background"), &bottom), (&top, &above)], &meta);
+        let p = project(&text);
+        assert_eq!(p.size, Vec2::new(3, 1));
+        assert_eq!(p.meta, meta);
+        assert!(p.layers[0].0 == Layer::new("Background") && p.layers[0].1 == bottom);
+        assert!(p.layers[1].0 == top && p.layers[1].1 == above);
+        assert_eq!(write(p.size, &[(&p.layers[0].0, &p.layers[0].1), (&p.layers[1].0, &p.layers[1].1)], &p.meta), text);
+    }
+
+    #[test]
+    fn v2_is_one_layer_without_alpha() {
+        let p = project("KKUN 2\nsize 2x1\n# a comment\nauthor fi-le\ncells\na000000ffffffb0000ff123456\n");
+        assert_eq!(p.layers.len(), 1);
+        let cells = &p.layers[0].1;
+        assert_eq!((cells[0].symbol, cells[1].symbol), ('a', 'b'));
+        assert!(cells[1].color == Color::Rgb(0, 0, 255) && cells[1].backcolor == Color::Rgb(0x12, 0x34, 0x56));
+        assert!(cells.iter().all(|c| c.alpha == 255 && !c.no_symbol && !c.no_color));
+        assert_eq!(p.meta, vec![("author".to_string(), "fi-le".to_string())]);
+    }
+
+    #[test]
+    fn v1_still_reads() {
+        let p = project("RGB\n0002x0001\n\t001002003|255255255|x\t000000000|010020030|\t\n");
+        let cells = &p.layers[0].1;
+        assert!(cells[0].color == Color::Rgb(1, 2, 3) && cells[0].symbol == 'x');
+        assert!(cells[1].backcolor == Color::Rgb(10, 20, 30) && cells[1].symbol == ' ');
+
+        let p = project(include_str!("assets/sargent.kkun"));
+        assert_eq!(p.size, Vec2::new(54, 37));
+        assert_eq!(p.layers[0].1.len(), 54 * 37);
+    }
+
+    #[test]
+    fn errors_point_at_the_problem() {
+        fails_at("hello", 1, 1);
+        fails_at("KKUN 9\n", 1, 6);
+        fails_at("KKUN 3\nsize 2y1\n", 2, 6);
+        fails_at("KKUN 3\nsize 2x1\nlayer a\nopacity 200\ncells\n", 4, 9);
+        fails_at("KKUN 3\nsize 2x1\nlayer a\ncells\na000000ffffffffbzz0000ffffffff\n", 5, 17);
+        fails_at("KKUN 3\nsize 2x1\nlayer a\ncells\na000000ffffffff\n", 5, 16);
+        fails_at("KKUN 3\nsize 2x2\nlayer a\ncells\na000000ffffffffb000000ffffffff\n", 6, 1);
+        fails_at("RGB\n0002x0001\n\t001002003|255255255|x\t000000000#010020030|\t\n", 3, 33);
+    }
+
+    #[test]
+    fn huge_sizes_fail_without_allocating() {
+        fails_at("KKUN 3\nsize 9999x9999\nlayer a\ncells\n", 5, 1);
+        fails_at("RGB\n9999x9999\n\t001002003|255255255|x\n", 3, 23);
+    }
+}
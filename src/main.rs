@@ -2,7 +2,17 @@
 // Everything in this is based on a minesweeper coding example of this program's parent, the TUI library cursive.
 // Going forward, segmenting this more might make sense; maybe move the help section to another file, for example.
 
+mod blend;
+mod brush;
 mod canvas;
+mod dither;
+mod export;
+mod font;
+mod history;
+mod import;
+mod kkun;
+mod palette;
+mod shapes;
 
 use std::fs;
 use std::env;
@@ -17,6 +27,7 @@ use cursive::Cursive;
 use cursive::Vec2;
 use cursive::backends;
 use cursive_buffered_backend::BufferedBackend;
+use image::imageops::FilterType;
 
 fn backend() -> Box<BufferedBackend> {
     let crossterm_backend = backends::crossterm::Backend::init().unwrap();
@@ -44,6 +55,7 @@ fn main() {
     siv.run_with(|| {backend()});
 }
 
+#[allow(clippy::single_match)]
 fn show_options(siv: &mut Cursive) {
     siv.add_layer(
         Dialog::new()
@@ -125,11 +137,163 @@ fn interpret_command (s: &mut Cursive, name: &str) {
     let re_brush = Regex::new("brush .").unwrap();
     let re_save_unicode = Regex::new("save .+[.]txt").unwrap();
     let re_save_image = Regex::new("save .+[.](jpg|png|jpeg)").unwrap();
+    let re_save_ansi = Regex::new("save .+[.]ans").unwrap();
+    let re_save_html = Regex::new("save .+[.]html?").unwrap();
+    let re_save_svg = Regex::new("save .+[.]svg").unwrap();
     let re_save = Regex::new("save .+([.]kkun|)").unwrap();
     let re_load = Regex::new("load .+([.]kkun|)").unwrap();
     let re_load_image = Regex::new("load .+[.](jpg|png|jpeg)").unwrap();
+    let re_load_ansi = Regex::new("load .+[.]ans").unwrap();
+    let re_load_text = Regex::new("load .+[.]txt").unwrap();
 
-    if re_brush.is_match(name) {
+    if name == "brush" {
+        let canvas: ViewRef<canvas::CanvasView> = s.find_name("canvas").unwrap();
+        let brush = *canvas.get_brush().borrow();
+
+        give_feedback(s, format!("Painting '{}' with the {:?} tool, size {} {:?}.", brush.symbol, brush.tool, brush.size, brush.shape));
+    }
+
+    else if name == "size" || name.starts_with("size ") {
+        // How many cells across the brush and the eraser are.
+        let canvas: ViewRef<canvas::CanvasView> = s.find_name("canvas").unwrap();
+        let brush = canvas.get_brush();
+
+        match name[4..].trim().parse::<usize>() {
+            Ok(n) if (1..=50).contains(&n) => {
+                brush.borrow_mut().size = n;
+                clear_pseudoconsole(s);
+            }
+            _ if name == "size" => give_feedback(s, format!("The brush is {} cells across.", brush.borrow().size)),
+            _ => give_feedback(s, "Try something like: size 5. Brushes go from 1 to 50 cells.".to_string()),
+        }
+    }
+
+    else if name == "shape" || name.starts_with("shape ") {
+        let canvas: ViewRef<canvas::CanvasView> = s.find_name("canvas").unwrap();
+        let shape = match name[5..].trim() {
+            "square" => Some(brush::Shape::Square),
+            "circle" => Some(brush::Shape::Circle),
+            "line" => Some(brush::Shape::Line),
+            _ => None,
+        };
+
+        match shape {
+            Some(shape) => {
+                canvas.get_brush().borrow_mut().shape = shape;
+                clear_pseudoconsole(s);
+            }
+            None => give_feedback(s, "Try one of: shape square, shape circle or shape line. For your own shape, try stamp.".to_string()),
+        }
+    }
+
+    else if name == "stamp" {
+        // Takes the selection as the brush, holes and all.
+        let mut canvas: ViewRef<canvas::CanvasView> = s.find_name("canvas").unwrap();
+        if canvas.set_stamp() {
+            canvas.get_brush().borrow_mut().shape = brush::Shape::Stamp;
+            canvas.set_tool(canvas::Tool::Draw);
+            drop(canvas);
+            give_feedback(s, "Click or drag to stamp. shape square goes back to a normal brush.".to_string());
+        }
+        else {
+            drop(canvas);
+            give_feedback(s, "Select or copy something first, it becomes the stamp.".to_string());
+        }
+    }
+
+    else if name == "dither" || name.starts_with("dither -") {
+        // Takes the same palette and dithering flags as loading an image: xterm's 16 colours with Floyd-Steinberg unless told otherwise.
+        let mut colors = None;
+        let mut method = dither::Dither::FloydSteinberg;
+        let mut words = name.split_whitespace().skip(1);
+        let mut valid = true;
+
+        while let Some(flag) = words.next() {
+            match flag {
+                "-none" => method = dither::Dither::None,
+                _ => valid &= palette_flag(flag, &mut words, &mut colors, &mut method) == Some(true),
+            }
+        }
+
+        if valid {
+            let colors = colors.unwrap_or_else(dither::xterm_16);
+            s.call_on_name("canvas", |view: &mut canvas::CanvasView| {view.dither(&colors, method)});
+            clear_pseudoconsole(s);
+        }
+        else {
+            give_feedback(s, "Try something like: dither -256 -bayer or dither -colors 000000,ffffff -atkinson".to_string());
+        }
+    }
+
+    else if name == "braille" || name.starts_with("braille ") {
+        // Picks the dots the braille tool paints, numbered like in braille: 1 to 3 down the left, 4 to 6 down the right, then 7 and 8 below.
+        let digits: Vec<u32> = name[7..].chars().filter(|c| !c.is_whitespace()).map(|c| c.to_digit(10).unwrap_or(0)).collect();
+
+        if digits.iter().all(|d| (1..=8).contains(d)) {
+            let mut canvas: ViewRef<canvas::CanvasView> = s.find_name("canvas").unwrap();
+            if !digits.is_empty() {
+                canvas.get_brush().borrow_mut().dots = digits.iter().fold(0, |dots, d| dots | 1 << (d - 1));
+            }
+            canvas.set_tool(canvas::Tool::Braille);
+            give_feedback(s, "Click or drag to add dots, start on a cell that has them all to take them away.".to_string());
+        }
+        else {
+            give_feedback(s, "Try something like: braille 1 4 7".to_string());
+        }
+    }
+
+    else if name == "alpha" || name.starts_with("alpha ") {
+        // How much of the background the brush paints, in percent. Below 100 the layers underneath show through, at 0 there is no background at all.
+        let canvas: ViewRef<canvas::CanvasView> = s.find_name("canvas").unwrap();
+        let brush = canvas.get_brush();
+
+        match name[5..].trim().trim_end_matches('%').parse::<u32>() {
+            Ok(p) if p <= 100 => {
+                brush.borrow_mut().alpha = (p * 255 / 100) as u8;
+                clear_pseudoconsole(s);
+            }
+            _ if name == "alpha" => give_feedback(s, format!("The brush paints {}% of its background.", brush.borrow().alpha as u32 * 100 / 255)),
+            _ => give_feedback(s, "Try something like: alpha 50".to_string()),
+        }
+    }
+
+    else if name == "erase" || name.starts_with("erase -") {
+        // -s only takes away symbols, -f only character colours, -b only backgrounds. Without a flag it's everything.
+        let mut canvas: ViewRef<canvas::CanvasView> = s.find_name("canvas").unwrap();
+        let part = match name.split_whitespace().nth(1) {
+            None | Some("-a") => Some(canvas::Part::All),
+            Some("-s") => Some(canvas::Part::Symbols),
+            Some("-f") => Some(canvas::Part::Foreground),
+            Some("-b") => Some(canvas::Part::Background),
+            _ => None,
+        };
+
+        match part {
+            Some(p) => {
+                canvas.get_brush().borrow_mut().erase = p;
+                canvas.set_tool(canvas::Tool::Erase);
+                give_feedback(s, "Click or drag to erase. While drawing, the right mouse button erases too.".to_string());
+            }
+            None => give_feedback(s, "Try something like: erase -b".to_string()),
+        }
+    }
+
+    else if name == "blend" || name.starts_with("blend ") {
+        // How the brush mixes its colours with what it paints over.
+        let canvas: ViewRef<canvas::CanvasView> = s.find_name("canvas").unwrap();
+        let brush = canvas.get_brush();
+
+        match blend::Blend::parse(name[5..].trim()) {
+            Some(b) => {
+                brush.borrow_mut().blend = b;
+                clear_pseudoconsole(s);
+            }
+            None if name == "blend" => give_feedback(s, format!("The brush blends {}.", brush.borrow().blend.name())),
+            None => give_feedback(s, "Try one of: blend normal, multiply, screen, overlay, add, darken, lighten or difference".to_string()),
+        }
+    }
+
+    else if re_brush.is_match(name) {
         s.call_on_name("canvas", |view: &mut canvas::CanvasView| {view.changebrushsymbol(name.chars().nth(6).unwrap())});
     }
 
@@ -154,24 +318,162 @@ fn interpret_command (s: &mut Cursive, name: &str) {
     }
 
     else if re_save_image.is_match(name) {
+        // Flags after the filename: -r renders symbols with their colours, -cell 8x16 sets how many pixels a cell is and implies -r.
         let filename = get_filename(name.to_string());
+        let mut render: Option<Vec2> = None;
+        let mut words = get_flags(name).into_iter();
+        let mut valid = true;
+
+        while let Some(flag) = words.next() {
+            match flag {
+                "-r" => render = render.or(Some(Vec2::new(8, 16))),
+                "-cell" => match words.next().and_then(|c| c.split_once('x')).map(|(w, h)| (w.parse::<usize>(), h.parse::<usize>())) {
+                    Some((Ok(w), Ok(h))) if (1..=64).contains(&w) && (1..=64).contains(&h) => render = Some(Vec2::new(w, h)),
+                    _ => valid = false,
+                },
+                _ => valid = false,
+            }
+        }
+
         let mut success = false;
+        let mut missing = 0;
+        if valid {
+            s.call_on_name("canvas", |view: &mut canvas::CanvasView| {
+                let img = match render {
+                    Some(cell) if view.get_width() as u64 * cell.x as u64 * view.get_height() as u64 * cell.y as u64 <= 1 << 27 => {
+                        let (img, m) = view.torender(cell);
+                        missing = m;
+                        image::DynamicImage::ImageRgb8(img)
+                    }
+                    Some(_) => return,
+                    None => image::DynamicImage::ImageRgba8(view.toimage()),
+                };
 
-        s.call_on_name("canvas", |view: &mut canvas::CanvasView| {
-            let img = view.toimage();
+                let mut path = env::current_dir().unwrap();
+                path.push(&filename);
+                let result = img.save(path);
+                match result {
+                    Ok(_i) => success = true,
+                    Err(_e) => {},
+                }
+            });
+        }
 
-            let mut path = env::current_dir().unwrap();
-            path.push(&filename);
-            let result = img.save(path);
-            match result {
-                Ok(_i) => success = true,
-                Err(_e) => {},
+        if !valid {give_feedback(s, "Try something like: save art.png -r or save art.png -cell 8x16".to_string());}
+        else if success && missing > 0 {give_feedback(s, format!("Saved {} as image. {} symbols aren't in the font and show as boxes.", filename, missing));}
+        else if success {give_feedback(s, format!("Saved {} as image.", filename));}
+        else if render.is_some() {give_feedback(s, "That image would be too large, try smaller cells with -cell.".to_string());}
+        else {give_feedback(s, "Something went wrong.\n  Does the filename contain weird characters?".to_string());}
+
+    }
+
+    else if re_save_ansi.is_match(name) {
+        // Flags after the filename: -256 or -16 for terminals with fewer colours, -m to only write colours where they change.
+        let filename = get_filename(name.to_string());
+        let mut options = export::AnsiOptions::default();
+        let mut valid = true;
+
+        for flag in get_flags(name) {
+            match flag {
+                "-256" => options.depth = export::ColorDepth::Xterm256,
+                "-16" => options.depth = export::ColorDepth::Xterm16,
+                "-m" => options.minimal = true,
+                _ => valid = false,
             }
-        });
+        }
+
+        let mut success = false;
+        if valid {
+            s.call_on_name("canvas", |view: &mut canvas::CanvasView| {
+                let text = view.toansi(options);
+
+                let mut path = env::current_dir().unwrap();
+                path.push(&filename);
+                success = fs::write(path, text).is_ok();
+            });
+        }
+
+        if !valid {give_feedback(s, "Try something like: save art.ans -256 -m".to_string());}
+        else if success {give_feedback(s, format!("Saved {} with colours, try cat {} in a terminal.", filename, filename));}
+        else {give_feedback(s, "Something went wrong.\n  Does the filename contain weird characters?".to_string());}
+    }
+
+    else if re_save_html.is_match(name) {
+        // Flags after the filename: -font Iosevka,Menlo picks the fonts, -lh 1.2 the line height and -bg 202020 the page colour.
+        let filename = get_filename(name.to_string());
+        let mut options = export::HtmlOptions::default();
+        let mut words = get_flags(name).into_iter();
+        let mut valid = true;
+
+        while let Some(flag) = words.next() {
+            match flag {
+                "-font" => match words.next() {
+                    Some(f) if f.chars().all(|c| c.is_alphanumeric() || c == ',' || c == '-') => options.font = f.to_string(),
+                    _ => valid = false,
+                },
+                "-lh" => match words.next().map(|l| l.parse::<f32>()) {
+                    Some(Ok(l)) if l > 0. && l < 10. => options.line_height = l,
+                    _ => valid = false,
+                },
+                "-bg" => match words.next().map(|c| u32::from_str_radix(c.trim_start_matches('#'), 16)) {
+                    Some(Ok(c)) if c <= 0xffffff => options.background = ((c >> 16) as u8, (c >> 8) as u8, c as u8),
+                    _ => valid = false,
+                },
+                _ => valid = false,
+            }
+        }
+
+        let mut success = false;
+        if valid {
+            s.call_on_name("canvas", |view: &mut canvas::CanvasView| {
+                let text = view.tohtml(&options);
+
+                let mut path = env::current_dir().unwrap();
+                path.push(&filename);
+                success = fs::write(path, text).is_ok();
+            });
+        }
 
-        if success {give_feedback(s, format!("Saved {} as image.", filename));}
+        if !valid {give_feedback(s, "Try something like: save art.html -font Iosevka -lh 1.1 -bg 202020".to_string());}
+        else if success {give_feedback(s, format!("Saved {} as a web page.", filename));}
         else {give_feedback(s, "Something went wrong.\n  Does the filename contain weird characters?".to_string());}
+    }
+
+    else if re_save_svg.is_match(name) {
+        // Flags after the filename: -aspect 2 for how tall a cell is compared to its width, -font like for web pages.
+        let filename = get_filename(name.to_string());
+        let mut options = export::SvgOptions::default();
+        let mut words = get_flags(name).into_iter();
+        let mut valid = true;
+
+        while let Some(flag) = words.next() {
+            match flag {
+                "-aspect" => match words.next().map(|a| a.parse::<f32>()) {
+                    Some(Ok(a)) if (0.1..=10.).contains(&a) => options.aspect = a,
+                    _ => valid = false,
+                },
+                "-font" => match words.next() {
+                    Some(f) if f.chars().all(|c| c.is_alphanumeric() || c == ',' || c == '-') => options.font = f.to_string(),
+                    _ => valid = false,
+                },
+                _ => valid = false,
+            }
+        }
+
+        let mut success = false;
+        if valid {
+            s.call_on_name("canvas", |view: &mut canvas::CanvasView| {
+                let text = view.tosvg(&options);
+
+                let mut path = env::current_dir().unwrap();
+                path.push(&filename);
+                success = fs::write(path, text).is_ok();
+            });
+        }
 
+        if !valid {give_feedback(s, "Try something like: save art.svg -aspect 2 -font Iosevka".to_string());}
+        else if success {give_feedback(s, format!("Saved {} as a vector image.", filename));}
+        else {give_feedback(s, "Something went wrong.\n  Does the filename contain weird characters?".to_string());}
     }
 
     else if re_save.is_match(name) {
@@ -195,39 +497,309 @@ fn interpret_command (s: &mut Cursive, name: &str) {
     }
 
     else if re_load_image.is_match(name) {
+        // Flags after the filename: -h for half blocks, twice the detail from top to bottom.
+        // -a picks symbols by how dark the picture is, -ramp .:=#@ changes them (_ is a blank), -ink colour|mono|inverted colours them.
+        // -b draws the dark parts in braille dots, -t 100 sets how dark counts as dark and -d dithers.
+        // -16, -256 or -colors ff0000,ffffff bring the colours down to a palette, -fs, -atkinson or -bayer dither them.
+        // -size 80x40, -width 80 or -height 40 set the size in cells, -fit the size the canvas has now, otherwise it's the size it was made with.
+        // -filter nearest|linear|cubic|gaussian|lanczos picks how pixels are resampled, -aspect 2 how tall cells are, -crop fills the size and cuts off the rest.
+        // -at 10,5 or -in (the selection) put it over the canvas like a paste instead of replacing it, -bg or -fg to only set those colours.
         let filename = get_filename(name.to_string());
+        let mut options = import::ImageOptions::default();
+        let mut at: Option<Vec2> = None;
+        let mut part: Option<canvas::Part> = None;
+        let mut words = get_flags(name).into_iter();
+        let mut valid = true;
+
+        while let Some(flag) = words.next() {
+            match flag {
+                "-h" => options.mode = import::ImageMode::HalfBlocks,
+                "-a" | "-ramp" | "-ink" => {
+                    // The last two imply the first.
+                    if !matches!(options.mode, import::ImageMode::Ascii(_)) {
+                        options.mode = import::ImageMode::Ascii(import::AsciiOptions::default());
+                    }
+                    let value = if flag == "-a" {None} else {words.next()};
+                    if let import::ImageMode::Ascii(ascii) = &mut options.mode {
+                        match (flag, value) {
+                            ("-a", _) => {},
+                            ("-ramp", Some(r)) => ascii.ramp = r.chars().map(|c| if c == '_' {' '} else {c}).collect(),
+                            ("-ink", Some("colour")) | ("-ink", Some("color")) => ascii.ink = import::Ink::Original,
+                            ("-ink", Some("mono")) => ascii.ink = import::Ink::Mono,
+                            ("-ink", Some("inverted")) => ascii.ink = import::Ink::Inverted,
+                            _ => valid = false,
+                        }
+                    }
+                }
+                "-b" | "-t" | "-d" => {
+                    // Like above, the last two imply the first.
+                    if !matches!(options.mode, import::ImageMode::Braille(_)) {
+                        options.mode = import::ImageMode::Braille(import::BrailleOptions::default());
+                    }
+                    let value = if flag == "-t" {words.next()} else {None};
+                    if let import::ImageMode::Braille(braille) = &mut options.mode {
+                        match (flag, value.map(|t| t.parse::<u8>())) {
+                            ("-b", _) => {},
+                            ("-t", Some(Ok(t))) => braille.threshold = t,
+                            ("-d", _) => braille.dither = true,
+                            _ => valid = false,
+                        }
+                    }
+                }
+                "-size" => match words.next().and_then(|c| c.split_once('x')).map(|(w, h)| (w.parse::<usize>(), h.parse::<usize>())) {
+                    Some((Ok(w), Ok(h))) if (1..=2000).contains(&w) && (1..=2000).contains(&h) => {options.width = Some(w); options.height = Some(h);}
+                    _ => valid = false,
+                },
+                "-width" | "-height" => match words.next().map(|n| n.parse::<usize>()) {
+                    Some(Ok(n)) if (1..=2000).contains(&n) => if flag == "-width" {options.width = Some(n)} else {options.height = Some(n)},
+                    _ => valid = false,
+                },
+                "-fit" => {
+                    let mut canvas: ViewRef<canvas::CanvasView> = s.find_name("canvas").unwrap();
+                    options.width = Some(canvas.get_width() as usize);
+                    options.height = Some(canvas.get_height() as usize);
+                }
+                "-filter" => match words.next() {
+                    Some("nearest") => options.filter = FilterType::Nearest,
+                    Some("linear") => options.filter = FilterType::Triangle,
+                    Some("cubic") => options.filter = FilterType::CatmullRom,
+                    Some("gaussian") => options.filter = FilterType::Gaussian,
+                    Some("lanczos") => options.filter = FilterType::Lanczos3,
+                    _ => valid = false,
+                },
+                "-aspect" => match words.next().map(|a| a.parse::<f32>()) {
+                    Some(Ok(a)) if (0.1..=10.).contains(&a) => options.aspect = a,
+                    _ => valid = false,
+                },
+                "-crop" => options.crop = true,
+                "-at" => match words.next().and_then(|c| c.split_once(',')).map(|(x, y)| (x.parse::<usize>(), y.parse::<usize>())) {
+                    Some((Ok(x), Ok(y))) => at = Some(Vec2::new(x, y)),
+                    _ => valid = false,
+                },
+                "-in" => {
+                    let canvas: ViewRef<canvas::CanvasView> = s.find_name("canvas").unwrap();
+                    match canvas.get_selection() {
+                        Some((min, max)) => {
+                            at = Some(min);
+                            options.width = Some(max.x - min.x + 1);
+                            options.height = Some(max.y - min.y + 1);
+                        }
+                        None => valid = false,
+                    }
+                }
+                "-bg" => part = Some(canvas::Part::Background),
+                "-fg" => part = Some(canvas::Part::Foreground),
+                _ => valid &= palette_flag(flag, &mut words, &mut options.colors, &mut options.dither) == Some(true),
+            }
+        }
+        if options.dither != dither::Dither::None && options.colors.is_none() {
+            options.colors = Some(dither::xterm_16());
+        }
 
         let mut path = env::current_dir().unwrap();
         path.push(&filename);
         let img = image::open(path);
         match img {
-            Ok(i) => {s.call_on_name("canvas", |view: &mut canvas::CanvasView| {view.fromimage(i, None);});
+            Ok(_) if !valid => {give_feedback(s, "Try something like: load photo.png -h -width 60 or load photo.png -a -ramp _.:=#@ -ink mono".to_string());}
+            Ok(i) if at.is_some() || part.is_some() => {
+                s.call_on_name("canvas", |view: &mut canvas::CanvasView| {view.pasteimage(i, &options, at.unwrap_or_else(Vec2::zero), part)});
+                give_feedback(s, format!("Loaded {}. Drag it into place and click elsewhere to put it down.", filename))}
+            Ok(i) => {s.call_on_name("canvas", |view: &mut canvas::CanvasView| {view.loadimage(i, &options)});
                       give_feedback(s, format!("Loaded {}.", filename))}
             Err(e) => {give_feedback(s, format!("{}.", e));}
         }
     }
 
+    else if re_load_ansi.is_match(name) {
+        let filename = get_filename(name.to_string());
+        let mut path = env::current_dir().unwrap();
+        path.push(&filename);
+
+        match fs::read(path) {
+            Ok(bytes) => {let problems = s.call_on_name("canvas", |view: &mut canvas::CanvasView| {view.fromansi(&bytes)}).unwrap_or_default();
+                          if problems.is_empty() {give_feedback(s, format!("Loaded {}.", filename));}
+                          else {give_feedback(s, format!("Loaded {}, but skipped what kakikun doesn't understand: {}.", filename, problems.join(", ")));}}
+            Err(e) => {give_feedback(s, format!("{}.", e));}
+        }
+    }
+
+    else if re_load_text.is_match(name) {
+        let filename = get_filename(name.to_string());
+        let mut path = env::current_dir().unwrap();
+        path.push(&filename);
+
+        match fs::read_to_string(path) {
+            Ok(t) => {s.call_on_name("canvas", |view: &mut canvas::CanvasView| {view.fromtext(&t)});
+                      give_feedback(s, format!("Loaded {}.", filename));}
+            Err(e) => {give_feedback(s, format!("Couldn't load {}, {}.", filename, e));}
+        }
+    }
+
     else if re_load.is_match(name) {
 
         let filename = get_filename(name.to_string());
-        let mut i = 1;
-        for n in name.split_whitespace() {
-            if i == 2 {
-                let mut path = env::current_dir().unwrap();
-                path.push(&filename);
+        let mut path = env::current_dir().unwrap();
+        path.push(&filename);
 
-                let text = fs::read_to_string(path);
-                match text {
-                    Ok(i) => {s.call_on_name("canvas", |view: &mut canvas::CanvasView| {view.fromfile(i);});
-                              give_feedback(s, format!("Loaded {}.", n))}
-                    Err(e) => {give_feedback(s, format!("{}.", e));}
-                }
+        let text = fs::read_to_string(path);
+        match text {
+            Ok(t) => {let result = s.call_on_name("canvas", |view: &mut canvas::CanvasView| {view.fromfile(&t)});
+                      match result {
+                          Some(Err(e)) => give_feedback(s, format!("Couldn't load {}, {}.", filename, e)),
+                          _ => give_feedback(s, format!("Loaded {}.", filename)),
+                      }}
+            Err(e) => {give_feedback(s, format!("{}.", e));}
+        }
+    }
+
+
+    else if name == "fill" || name.starts_with("fill -") {
+        // Flags change the bucket and stay set: -b, -f, -s or -c pick what has to match, -t 20 the tolerance, -4 or -8 the connectivity.
+        let mut canvas: ViewRef<canvas::CanvasView> = s.find_name("canvas").unwrap();
+        let mut fill = canvas.get_fill();
+        let mut words = name.split_whitespace().skip(1);
+        let mut valid = true;
+
+        while let Some(flag) = words.next() {
+            match flag {
+                "-b" => fill.mode = canvas::FillMode::Background,
+                "-f" => fill.mode = canvas::FillMode::Foreground,
+                "-s" => fill.mode = canvas::FillMode::Symbol,
+                "-c" => fill.mode = canvas::FillMode::Cell,
+                "-4" => fill.diagonal = false,
+                "-8" => fill.diagonal = true,
+                "-t" => match words.next().map(|t| t.parse::<u8>()) {
+                    Some(Ok(t)) => fill.tolerance = t,
+                    _ => valid = false,
+                },
+                _ => valid = false,
+            }
+        }
+
+        if valid {
+            canvas.set_fill(fill);
+            canvas.set_tool(canvas::Tool::Fill);
+            give_feedback(s, "Click on the canvas to fill.".to_string());
+        }
+        else {
+            give_feedback(s, "Try something like: fill -b -t 20 -8".to_string());
+        }
+    }
+
+    else if name == "history" || name.starts_with("history -") {
+        // -s 500 sets how many steps are remembered, -m 64 how many megabytes they may take up.
+        let mut canvas: ViewRef<canvas::CanvasView> = s.find_name("canvas").unwrap();
+        let history = canvas.get_history();
+        let (mut depth, mut budget) = history.limits();
+        let mut words = name.split_whitespace().skip(1);
+        let mut valid = true;
+
+        while let Some(flag) = words.next() {
+            match (flag, words.next().map(|n| n.parse::<usize>())) {
+                ("-s", Some(Ok(n))) => depth = n,
+                ("-m", Some(Ok(n))) => match n.checked_mul(1 << 20) {
+                    Some(bytes) => budget = bytes,
+                    None => valid = false,
+                },
+                _ => valid = false,
             }
+        }
 
-            i += 1;
+        if valid {
+            history.set_limits(depth, budget);
+            let (steps, bytes) = history.usage();
+            give_feedback(s, format!("Remembering {} of {} steps, {} of {} kB.", steps, depth, bytes >> 10, budget >> 10));
+        }
+        else {
+            give_feedback(s, "Try something like: history -s 500 -m 64".to_string());
         }
     }
 
+    else if name == "layers" || name == "layer" || name.starts_with("layer ") {
+        // layer add, delete, rename, up, down, hide, show, lock, unlock, opacity 50 and merge work on the active layer, layer 2 picks another one.
+        let mut canvas: ViewRef<canvas::CanvasView> = s.find_name("canvas").unwrap();
+        let mut layer = canvas.get_layers()[canvas.get_active()].clone();
+        let mut words = name.splitn(3, ' ').skip(1);
+        let command = words.next().unwrap_or("");
+        let value = words.next().unwrap_or("").trim();
+
+        let done = match command {
+            "" => true,
+            "add" => {
+                let default = format!("Layer {}", canvas.get_layers().len() + 1);
+                canvas.add_layer(if value.is_empty() {&default} else {value});
+                true
+            }
+            "delete" => canvas.delete_layer(),
+            "up" => canvas.move_layer(true),
+            "down" => canvas.move_layer(false),
+            "merge" => canvas.merge_layer(),
+            "rename" if !value.is_empty() => {layer.name = value.to_string(); canvas.set_layer(layer); true},
+            "hide" | "show" => {layer.visible = command == "show"; canvas.set_layer(layer); true},
+            "lock" | "unlock" => {layer.locked = command == "lock"; canvas.set_layer(layer); true},
+            "opacity" => match value.parse::<u8>() {
+                Ok(o) if o <= 100 => {layer.opacity = o; canvas.set_layer(layer); true},
+                _ => false,
+            },
+            "blend" => match blend::Blend::parse(value) {
+                Some(b) => {layer.blend = b; canvas.set_layer(layer); true},
+                None => false,
+            },
+            n => match n.parse::<usize>() {
+                Ok(i) if i > 0 => canvas.select_layer(i - 1),
+                _ => false,
+            },
+        };
+
+        if done {
+            // Listed from the bottom up, the active one in brackets.
+            let active = canvas.get_active();
+            let listed: Vec<String> = canvas.get_layers().iter().enumerate().map(|(i, l)| {
+                let mut text = format!("{} {}", i + 1, l.name);
+                if !l.visible {text.push_str(" hidden");}
+                if l.locked {text.push_str(" locked");}
+                if l.opacity != 100 {text.push_str(&format!(" {}%", l.opacity));}
+                if l.blend != blend::Blend::Normal {text.push_str(&format!(" {}", l.blend.name()));}
+                if i == active {format!("[{}]", text)} else {text}
+            }).collect();
+            drop(canvas);
+            give_feedback(s, format!("Layers: {}", listed.join(", ")));
+            clear_pseudoconsole(s);
+        }
+        else {
+            let refused = canvas.take_refused();
+            drop(canvas);
+            match command {
+                "delete" if refused => give_feedback(s, "A locked layer can't be deleted. Try: layer unlock".to_string()),
+                "delete" => give_feedback(s, "The last layer can't be deleted.".to_string()),
+                "merge" => give_feedback(s, "Merging needs an unlocked layer below this one, and this one unlocked too.".to_string()),
+                "up" | "down" => give_feedback(s, format!("This layer can't go further {}.", command)),
+                _ => give_feedback(s, "Try something like: layer add sketch, layer 2, layer hide, layer opacity 50 or layer blend multiply".to_string()),
+            }
+        }
+    }
+
+    else if name == "meta" || name.starts_with("meta ") {
+        // meta lists the project's metadata, meta author fi-le sets some and meta author alone removes it again.
+        let mut canvas: ViewRef<canvas::CanvasView> = s.find_name("canvas").unwrap();
+        let mut words = name.splitn(3, ' ').skip(1);
+
+        match (words.next(), words.next()) {
+            (Some(key), value) => {
+                if canvas.set_meta(key, value.unwrap_or("").trim()) {
+                    give_feedback(s, format!("Set {}, it will be saved with the project.", key));
+                }
+                else {
+                    give_feedback(s, format!("'{}' is used by the file format itself, try another name.", key));
+                }
+            }
+            (None, _) => {
+                let listed: Vec<String> = canvas.get_meta().iter().map(|(k, v)| format!("{}: {}", k, v)).collect();
+                if listed.is_empty() {give_feedback(s, "No metadata yet. Try: meta author your name".to_string());}
+                else {give_feedback(s, listed.join(", "));}
+            }
+        }
+    }
 
     else if name == "help" {
         s.add_layer(
@@ -246,6 +818,9 @@ fn interpret_command (s: &mut Cursive, name: &str) {
                             .child("\t", TextView::new("To choose a character other than a blank space to paint with, you can use this command:"))
                             .child("\t", TextView::new(""))
                             .child("brush ", TextView::new("Change the symbol with which you draw to any unicode character. Example: brush é"))
+                            .child("\t", TextView::new("Just typing brush tells you what you're painting with. It's also shown below the palette."))
+                            .child("size ", TextView::new("Makes the brush bigger, up to 50 cells. Example: size 5. While you paint, the cells it covers are shown inverted."))
+                            .child("shape ", TextView::new("Square, circle or line. Example: shape circle. stamp paints with what you selected instead."))
                             .child("\t", TextView::new(""))
                             .child("\t", TextView::new("Since there a quite a few more of those commands, feel free to refer to the sections below. Have fun!")),
                         )
@@ -260,13 +835,50 @@ fn interpret_command (s: &mut Cursive, name: &str) {
                                     ListView::new()
                                         .child("\t", TextView::new("Some general commands to try:"))
                                         .child("\t", TextView::new(""))
-                                        .child("back    | Ctrl+Z", TextView::new("Undoes one step of painting. Kakikun will at most remember 250 actions."))
+                                        .child("back    | Ctrl+Z", TextView::new("Undoes one step of painting. Kakikun remembers 250 actions by default."))
+                                        .child("forward | Ctrl+Y", TextView::new("Redoes what you just undid, until you paint something new."))
+                                        .child("history", TextView::new("Shows how much is remembered for back. Change it with -s for steps and -m for megabytes, like history -s 500 -m 64"))
                                         .child("pipette | Ctrl+P", TextView::new("Lets you pick colours and symbols from the canvas. Reverts to brush automatically."))
                                         .child("clear", TextView::new("Clears the canvas to white background."))
+                                        .child("fill    | Ctrl+B", TextView::new("Fills the area you click on with the brush. Type draw to paint normally again."))
+                                        .child("\t", TextView::new("Matches -b background (default), -f foreground, -s symbol or -c everything, -t 20 allows some tolerance, -8 also spreads diagonally."))
+                                        .child("line", TextView::new("Drag on the canvas to draw a straight line."))
+                                        .child("rect", TextView::new("Drag to draw a rectangle. Use rect -f for a filled one."))
+                                        .child("ellipse", TextView::new("Drag to draw an ellipse. Use ellipse -f for a filled one."))
+                                        .child("select", TextView::new("Drag to select a rectangle. Drag the selection to move it, click elsewhere to put it down."))
+                                        .child("copy", TextView::new("Copies the selection, with colours and all. cut also removes it from the canvas."))
+                                        .child("paste", TextView::new("Pastes what you copied. It floats above the canvas until you click elsewhere or deselect."))
+                                        .child("delete", TextView::new("Clears the selection to white background."))
+                                        .child("draw", TextView::new("Goes back to painting cell by cell."))
+                                        .child("erase   | Ctrl+E", TextView::new("Erases to white, or to see-through on upper layers. -s only symbols, -f only their colours, -b only backgrounds."))
+                                        .child("\t", TextView::new("While drawing, the right mouse button erases as well."))
+                                        .child("braille", TextView::new("Paints braille dots for fine lines. braille 1 4 picks the dots: 1 to 3 down the left, 4 to 6 down the right, 7 and 8 below."))
                                         .child("fill all", TextView::new("Fills the whole of the canvas with the current brush setting."))
                                         .child("save", TextView::new("Saves the background colours in image format, the characters as text or everything as a kakikun project."))
                                         .child("\t", TextView::new("Examples: save image.png, save img.jpg, save ascii_art.txt, save everything.kkun"))
+                                        .child("\t", TextView::new("Add -r to draw the symbols into the image too, -cell 8x16 to choose how many pixels each cell takes."))
+                                        .child("\t", TextView::new("Where no layer covers the canvas, a .png stays transparent."))
+                                        .child("\t", TextView::new("save art.ans keeps characters and colours for the terminal. Add -256 or -16 for fewer colours, -m for a smaller file."))
+                                        .child("\t", TextView::new("save art.html writes a web page. Options: -font Iosevka,Menlo, -lh 1.1 for the line height, -bg 202020 for the page colour."))
+                                        .child("\t", TextView::new("save art.svg keeps symbols and colours as a vector image. -aspect 2 sets how tall cells are, -font like above."))
                                         .child("load", TextView::new("Loads an image or a kakikun project. Scales images down to a console-friendly size."))
+                                        .child("\t", TextView::new("load photo.png -h uses half blocks, for twice the detail from top to bottom."))
+                                        .child("\t", TextView::new("load photo.png -a turns it into text art. -ramp _.:=#@ sets the symbols from light to dense, _ is a blank."))
+                                        .child("\t", TextView::new("-ink colour, mono or inverted sets how the symbols are coloured."))
+                                        .child("\t", TextView::new("load photo.png -b draws it in braille dots. -t 100 sets how dark a dot has to be, -d dithers."))
+                                        .child("\t", TextView::new("-16, -256 or -colors ff0000,ffffff bring the colours down to a palette, -fs, -atkinson or -bayer dither them."))
+                                        .child("\t", TextView::new("Pictures fit the canvas as it was made. -size 80x40, -width 80 or -height 40 choose a size in cells, -fit the current one."))
+                                        .child("\t", TextView::new("-filter nearest, linear, cubic, gaussian or lanczos for resampling, -aspect 2 for the cell shape, -crop to fill and cut off."))
+                                        .child("\t", TextView::new("-at 10,5 or -in the selection keeps the canvas and floats the picture over it. -bg or -fg only set those colours."))
+                                        .child("\t", TextView::new("Plain text from .txt files becomes symbols in the brush colours, or black on white."))
+                                        .child("\t", TextView::new("Text with colour escapes, like from other terminal art tools, loads from .ans files."))
+                                        .child("blend", TextView::new("How the brush mixes with what it paints over: normal, multiply, screen, overlay, add, darken, lighten or difference."))
+                                        .child("alpha", TextView::new("How much the background you paint covers the layers below, like alpha 50. alpha 0 paints no background at all."))
+                                        .child("layers", TextView::new("Lists the layers from the bottom up, the one you paint on in brackets."))
+                                        .child("layer", TextView::new("layer add sketch puts a see-through layer above, layer 2 paints on the second one from the bottom."))
+                                        .child("\t", TextView::new("layer rename, delete, up, down, hide, show, lock, unlock, opacity 50 and blend multiply change the one you paint on."))
+                                        .child("\t", TextView::new("layer merge flattens it into the one below. Saving as .kkun keeps the layers, everything else flattens them."))
+                                        .child("meta", TextView::new("Shows or sets metadata that is saved in kakikun projects. Example: meta author fi-le"))
                                         .child("theme", TextView::new("Loads a theme. light, dark and default are Available. Example: theme dark"))
                                         .child("quit", TextView::new("Closes the program."))
                                     )
@@ -293,6 +905,7 @@ fn interpret_command (s: &mut Cursive, name: &str) {
                                         .child("rotate hue", TextView::new("Shifts the hue around the colour wheel."))
                                         .child("contrast", TextView::new("Increases the contrast."))
                                         .child("decontrast", TextView::new("Decreases the contrast."))
+                                        .child("dither", TextView::new("Brings the colours down to xterm's 16. Takes -256, -colors ff0000,ffffff and -fs, -atkinson, -bayer or -none."))
                                         .child("", TextView::new(""))
                                         .child("rotate", TextView::new("Rotates by 90° clockwise. Squares off everything to keep the proportions, so use carefully."))
                                     )
@@ -321,12 +934,37 @@ fn interpret_command (s: &mut Cursive, name: &str) {
         "rotate hue" => {s.call_on_name("canvas", |view: &mut canvas::CanvasView| {view.huerotate(1)});},
         "contrast" => {s.call_on_name("canvas", |view: &mut canvas::CanvasView| {view.adjust_contrast(0.2)});},
         "decontrast" => {s.call_on_name("canvas", |view: &mut canvas::CanvasView| {view.adjust_contrast(-0.2)});},
+        "line" => {s.call_on_name("canvas", |view: &mut canvas::CanvasView| {view.set_tool(canvas::Tool::Line)});},
+        "rect" => {s.call_on_name("canvas", |view: &mut canvas::CanvasView| {view.set_tool(canvas::Tool::Rectangle)});},
+        "rect -f" => {s.call_on_name("canvas", |view: &mut canvas::CanvasView| {view.set_tool(canvas::Tool::FilledRectangle)});},
+        "ellipse" => {s.call_on_name("canvas", |view: &mut canvas::CanvasView| {view.set_tool(canvas::Tool::Ellipse)});},
+        "ellipse -f" => {s.call_on_name("canvas", |view: &mut canvas::CanvasView| {view.set_tool(canvas::Tool::FilledEllipse)});},
+        "select" => {s.call_on_name("canvas", |view: &mut canvas::CanvasView| {view.set_tool(canvas::Tool::Select)});},
+        "deselect" => {s.call_on_name("canvas", |view: &mut canvas::CanvasView| {view.deselect()});},
+        "copy" | "cut" | "paste" | "delete" => {
+            let done = s.call_on_name("canvas", |view: &mut canvas::CanvasView| {
+                match name {
+                    "copy" => view.copy_selection(),
+                    "cut" => view.cut_selection(),
+                    "paste" => view.paste(),
+                    _ => view.delete_selection(),
+                }
+            }).unwrap_or(false);
+
+            if done {clear_pseudoconsole(s);}
+            else if name == "paste" {give_feedback(s, "There is nothing to paste yet.".to_string());}
+            else {give_feedback(s, "Select something first, try: select".to_string());}
+        },
+        "draw" => {s.call_on_name("canvas", |view: &mut canvas::CanvasView| {view.set_tool(canvas::Tool::Draw)});},
         "pipette" => {s.call_on_name("canvas", |view: &mut canvas::CanvasView| {view.set_tool(canvas::Tool::Pipette)});},
-        "back" => {s.call_on_name("canvas", |view: &mut canvas::CanvasView| {view.back()});},
-        "sargent" => {s.call_on_name("canvas", |view: &mut canvas::CanvasView| {let sargent = include_bytes!("assets/sargent.kkun");
-                                                                                view.fromfile(String::from_utf8_lossy(sargent).to_string());});}
-        "fi-le" => {s.call_on_name("canvas", |view: &mut canvas::CanvasView| {let file = include_bytes!("assets/file.kkun");
-                                                                                view.fromfile(String::from_utf8_lossy(file).to_string());});}
+        "back" | "undo" => {s.call_on_name("canvas", |view: &mut canvas::CanvasView| {view.back()});},
+        "forward" | "redo" => {s.call_on_name("canvas", |view: &mut canvas::CanvasView| {view.forward()});},
+        "sargent" | "fi-le" => {let text = if name == "sargent" {include_str!("assets/sargent.kkun")} else {include_str!("assets/file.kkun")};
+                    let result = s.call_on_name("canvas", |view: &mut canvas::CanvasView| {view.fromfile(text)});
+                    match result {
+                        Some(Err(e)) => give_feedback(s, format!("Couldn't load the {} example, {}.", name, e)),
+                        _ => clear_pseudoconsole(s),
+                    }},
         "width" => {let mut canvas: ViewRef<canvas::CanvasView> = s.find_name("canvas").unwrap();
                     let width: u32 = canvas.get_width();
 
@@ -352,9 +990,18 @@ fn interpret_command (s: &mut Cursive, name: &str) {
     }
 
     match name {
-        "clear" | "fill all" | "grayscale" | "sargent" | "height" | "width" | "theme dark" | "theme light" | "fi-le" | "theme default" | "pipette"  => {clear_pseudoconsole(s);},
+        "clear" | "fill all" | "grayscale" | "height" | "width" | "theme dark" | "theme light" | "theme default" | "pipette" | "fill" | "draw" | "line" | "rect" | "rect -f" | "ellipse" | "ellipse -f" | "select" | "deselect" => {clear_pseudoconsole(s);},
         _ => {}
     }
+
+    // The canvas refuses anything that would paint over a locked layer, this says why nothing happened.
+    refused_feedback(s);
+}
+
+fn refused_feedback(siv: &mut Cursive) {
+    if siv.call_on_name("canvas", |view: &mut canvas::CanvasView| {view.take_refused()}).unwrap_or(false) {
+        give_feedback(siv, "This layer is locked. Try: layer unlock".to_string());
+    }
 }
 
 fn give_feedback(siv: &mut Cursive, s: String) {
@@ -368,12 +1015,36 @@ fn clear_pseudoconsole(siv: &mut Cursive) {
 }
 
 fn get_filename(input: String) -> String {
-    let words: Vec<&str> = input.split(' ').collect();
+    // The filename is the word right after save or load, anything after it are flags.
+    let words: Vec<&str> = input.split_whitespace().collect();
+
+    words.get(1).unwrap_or(&"").to_string()
 
-    words.last().clone().unwrap().to_string()
+}
+
+fn palette_flag<'a>(flag: &str, words: &mut impl Iterator<Item = &'a str>, colors: &mut Option<Vec<(u8, u8, u8)>>, method: &mut dither::Dither) -> Option<bool> {
+    // The flags for bringing colours down to a palette. None if flag isn't one of them, otherwise whether it was used right.
+    match flag {
+        "-16" => *colors = Some(dither::xterm_16()),
+        "-256" => *colors = Some(dither::xterm_256_all()),
+        "-colors" | "-colours" => match words.next().and_then(dither::parse_swatches) {
+            Some(c) => *colors = Some(c),
+            None => return Some(false),
+        },
+        "-fs" => *method = dither::Dither::FloydSteinberg,
+        "-atkinson" => *method = dither::Dither::Atkinson,
+        "-bayer" => *method = dither::Dither::Bayer,
+        _ => return None,
+    }
+
+    Some(true)
+}
 
+fn get_flags(input: &str) -> Vec<&str> {
+    input.split_whitespace().skip(2).collect()
 }
 
+#[allow(clippy::redundant_field_names)]
 fn theme_light(siv: &mut Cursive) {
     let mut palette = Palette::default();
     palette[Background] = Color::Rgb(191,171,150);
@@ -387,6 +1058,7 @@ fn theme_light(siv: &mut Cursive) {
     siv.set_theme(Theme {shadow: true, borders: BorderStyle::Simple, palette: palette});
 }
 
+#[allow(clippy::redundant_field_names)]
 fn theme_dark(siv: &mut Cursive) {
     let mut palette = Palette::default();
     palette[Background] = Color::Rgb(35,33,30);
@@ -400,6 +1072,7 @@ fn theme_dark(siv: &mut Cursive) {
     siv.set_theme(Theme {shadow: true, borders: BorderStyle::Simple, palette: palette});
 }
 
+#[allow(clippy::redundant_field_names)]
 fn theme_default(siv: &mut Cursive) {
     let palette = Palette::default();
     siv.set_theme(Theme {shadow: true, borders: BorderStyle::Simple, palette: palette});
@@ -408,12 +1081,16 @@ fn theme_default(siv: &mut Cursive) {
 fn new_canvas(siv: &mut Cursive, size: Vec2) {
     // This is where we set up the layout of the main painting. A current issue is that altough canvas size may change over this layers' lifetime, the palette et cetera stay the same.
     let _board = canvas::Board::new(size);
+    let brush = brush::Brush::shared(); // Every view of this canvas paints with the same brush.
     let picker_height = ((size.y as i32 + 25) / 2) - ((size.y as i32 - 25).abs() / 2); // This is just a fancy way to get the minimum of 25 and size.y
 
     // Let's add some fun keybindings
     siv.add_global_callback(Event::CtrlChar('z'), |s| {s.call_on_name("canvas", |view: &mut canvas::CanvasView| {view.back()});});
+    siv.add_global_callback(Event::CtrlChar('y'), |s| {s.call_on_name("canvas", |view: &mut canvas::CanvasView| {view.forward()});});
     siv.add_global_callback(Event::CtrlChar('p'), |s| {s.call_on_name("canvas", |view: &mut canvas::CanvasView| {view.set_tool(canvas::Tool::Pipette)});});
-    siv.add_global_callback(Event::CtrlChar('f'), |s| {s.call_on_name("canvas", |view: &mut canvas::CanvasView| {view.fliph()});});
+    siv.add_global_callback(Event::CtrlChar('f'), |s| {s.call_on_name("canvas", |view: &mut canvas::CanvasView| {view.fliph()}); refused_feedback(s);});
+    siv.add_global_callback(Event::CtrlChar('b'), |s| {s.call_on_name("canvas", |view: &mut canvas::CanvasView| {view.set_tool(canvas::Tool::Fill)});});
+    siv.add_global_callback(Event::CtrlChar('e'), |s| {s.call_on_name("canvas", |view: &mut canvas::CanvasView| {view.set_tool(canvas::Tool::Erase)});});
     siv.add_layer(
         Dialog::new()
             .title("kakikun - 描きくん")
@@ -429,8 +1106,8 @@ fn new_canvas(siv: &mut Cursive, size: Vec2) {
                             });
                         }))
 
-                        .child(canvas::PickView::new(10, picker_height as u8).with_name("picker"))
-                        .child(canvas::CanvasView::new(size).with_name("canvas")),
+                        .child(canvas::PickView::new(10, picker_height as u8, brush.clone()).with_name("picker"))
+                        .child(canvas::CanvasView::new(size, brush).with_name("canvas")),
                 ))
                 .child(
                     LinearLayout::horizontal()
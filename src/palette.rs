@@ -0,0 +1,65 @@
+// Colours as terminals know them. Everything on the canvas is 24-bit, this is for talking to the outside world.
+
+use cursive::theme::{BaseColor, Color};
+
+// The xterm defaults for the 16 base colours, dark ones first.
+pub const XTERM_16: [(u8, u8, u8); 16] = [
+    (0, 0, 0), (205, 0, 0), (0, 205, 0), (205, 205, 0), (0, 0, 238), (205, 0, 205), (0, 205, 205), (229, 229, 229),
+    (127, 127, 127), (255, 0, 0), (0, 255, 0), (255, 255, 0), (92, 92, 255), (255, 0, 255), (0, 255, 255), (255, 255, 255),
+];
+
+fn base_index(color: BaseColor) -> usize {
+    match color {
+        BaseColor::Black => 0,
+        BaseColor::Red => 1,
+        BaseColor::Green => 2,
+        BaseColor::Yellow => 3,
+        BaseColor::Blue => 4,
+        BaseColor::Magenta => 5,
+        BaseColor::Cyan => 6,
+        BaseColor::White => 7,
+    }
+}
+
+pub fn to_rgb(color: Color) -> (u8, u8, u8) {
+    // Whatever cursive colour we get, this is our best guess at what it looks like. The terminal default is taken to be white, like a blank canvas.
+    match color {
+        Color::Rgb(r, g, b) => (r, g, b),
+        Color::RgbLowRes(r, g, b) => (r * 51, g * 51, b * 51),
+        Color::Dark(c) => XTERM_16[base_index(c)],
+        Color::Light(c) => XTERM_16[base_index(c) + 8],
+        Color::TerminalDefault => (255, 255, 255),
+    }
+}
+
+pub fn xterm_256(n: u8) -> (u8, u8, u8) {
+    // The 256 colour palette: the 16 base colours, a 6x6x6 cube and 24 shades of grey.
+    let level = |v: u8| if v == 0 {0} else {55 + 40 * v};
+
+    match n {
+        0..=15 => XTERM_16[n as usize],
+        16..=231 => {
+            let i = n - 16;
+            (level(i / 36), level((i / 6) % 6), level(i % 6))
+        }
+        _ => {
+            let grey = 8 + 10 * (n - 232);
+            (grey, grey, grey)
+        }
+    }
+}
+
+fn distance(a: (u8, u8, u8), b: (u8, u8, u8)) -> u32 {
+    let d = |x: u8, y: u8| (x as i32 - y as i32).pow(2) as u32;
+    d(a.0, b.0) + d(a.1, b.1) + d(a.2, b.2)
+}
+
+pub fn nearest(palette: &[(u8, u8, u8)], rgb: (u8, u8, u8)) -> usize {
+    // The index of the palette colour closest to rgb.
+    (0..palette.len()).min_by_key(|&i| distance(palette[i], rgb)).unwrap_or(0)
+}
+
+pub fn nearest_256(rgb: (u8, u8, u8)) -> u8 {
+    // Only the cube and the greys are searched, the base colours look different in every terminal.
+    (16..=255).min_by_key(|&n| distance(xterm_256(n), rgb)).unwrap_or(16)
+}
@@ -0,0 +1,107 @@
+// Geometry for the shape tools. Everything here only says which cells belong to a shape, painting them is up to the canvas.
+
+use cursive::Vec2;
+
+pub fn line(a: Vec2, b: Vec2) -> Vec<Vec2> {
+    // Bresenham's line, so there are no gaps however far apart the two ends are.
+    let (mut x, mut y) = (a.x as i64, a.y as i64);
+    let (x1, y1) = (b.x as i64, b.y as i64);
+    let dx = (x1 - x).abs();
+    let dy = -(y1 - y).abs();
+    let sx = if x < x1 {1} else {-1};
+    let sy = if y < y1 {1} else {-1};
+    let mut err = dx + dy;
+
+    let mut cells = Vec::new();
+    loop {
+        cells.push(Vec2::new(x as usize, y as usize));
+        if x == x1 && y == y1 {
+            break;
+        }
+
+        let e2 = 2 * err;
+        if e2 >= dy {
+            err += dy;
+            x += sx;
+        }
+        if e2 <= dx {
+            err += dx;
+            y += sy;
+        }
+    }
+
+    cells
+}
+
+pub fn rectangle(a: Vec2, b: Vec2, filled: bool) -> Vec<Vec2> {
+    let (min, max) = (a.or_min(b), a.or_max(b));
+
+    (min.y..=max.y)
+        .flat_map(|y| (min.x..=max.x).map(move |x| Vec2::new(x, y)))
+        .filter(|p| filled || p.x == min.x || p.x == max.x || p.y == min.y || p.y == max.y)
+        .collect()
+}
+
+pub fn ellipse(a: Vec2, b: Vec2, filled: bool) -> Vec<Vec2> {
+    // The ellipse fits the box spanned by a and b. We test the centre of every cell in the box,
+    // the outline then is every inside cell that touches the outside, which keeps it free of gaps.
+    let (min, max) = (a.or_min(b), a.or_max(b));
+    let (w, h) = (max.x - min.x + 1, max.y - min.y + 1);
+    let (rx, ry) = (w as f32 / 2., h as f32 / 2.);
+
+    let inside = |x: i64, y: i64| {
+        if x < 0 || y < 0 || x >= w as i64 || y >= h as i64 {
+            return false;
+        }
+        let dx = (x as f32 + 0.5 - rx) / rx;
+        let dy = (y as f32 + 0.5 - ry) / ry;
+        dx * dx + dy * dy <= 1.
+    };
+
+    let mut cells = Vec::new();
+    for y in 0..h as i64 {
+        for x in 0..w as i64 {
+            if !inside(x, y) {
+                continue;
+            }
+            let edge = !inside(x - 1, y) || !inside(x + 1, y) || !inside(x, y - 1) || !inside(x, y + 1);
+            if filled || edge {
+                cells.push(Vec2::new(min.x + x as usize, min.y + y as usize));
+            }
+        }
+    }
+
+    cells
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lines_reach_both_ends_without_gaps() {
+        let ends = [((0, 0), (9, 3)), ((9, 3), (0, 0)), ((2, 8), (2, 1)), ((5, 5), (5, 5)), ((0, 7), (7, 0)), ((3, 0), (4, 9))];
+        for (a, b) in ends {
+            let (a, b) = (Vec2::from(a), Vec2::from(b));
+            let cells = line(a, b);
+            assert_eq!((cells[0], *cells.last().unwrap()), (a, b));
+
+            // Every cell is a step of one to the next, and as many as the longer side needs.
+            assert!(cells.windows(2).all(|w| w[0].x.abs_diff(w[1].x) <= 1 && w[0].y.abs_diff(w[1].y) <= 1 && w[0] != w[1]));
+            assert_eq!(cells.len(), a.x.abs_diff(b.x).max(a.y.abs_diff(b.y)) + 1);
+        }
+    }
+
+    #[test]
+    fn rectangles_and_ellipses() {
+        let (a, b) = (Vec2::new(5, 4), Vec2::new(1, 1));
+        assert_eq!(rectangle(a, b, true).len(), 20);
+        assert_eq!(rectangle(a, b, false).len(), 14);
+
+        let filled = ellipse(Vec2::new(0, 0), Vec2::new(8, 4), true);
+        let outline = ellipse(Vec2::new(0, 0), Vec2::new(8, 4), false);
+        assert!(outline.iter().all(|p| filled.contains(p)) && outline.len() < filled.len());
+        assert!(filled.contains(&Vec2::new(4, 2)) && !filled.contains(&Vec2::new(0, 0)));
+        assert!([(0, 2), (8, 2), (4, 0), (4, 4)].iter().all(|p| outline.contains(&Vec2::from(*p))));
+    }
+}